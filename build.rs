@@ -0,0 +1,23 @@
+// Generates the character level table from data/CharacterLevelTable.csv.
+use std::env;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+const LEVEL_TABLE: &str = "data/CharacterLevelTable.csv";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", LEVEL_TABLE);
+    let text = read_to_string(LEVEL_TABLE).expect("reading the character level table");
+    let mut clvls = Vec::new();
+    for (i, line) in text.lines().filter(|line| !line.trim().is_empty()).enumerate() {
+        let (lvl, clvl) = line.trim().split_once(',').unwrap_or_else(|| panic!("{}: bad line '{}'", LEVEL_TABLE, line));
+        let lvl: usize = lvl.parse().unwrap_or_else(|_| panic!("{}: bad level '{}'", LEVEL_TABLE, lvl));
+        let clvl: u16 = clvl.parse().unwrap_or_else(|_| panic!("{}: bad recipe level '{}'", LEVEL_TABLE, clvl));
+        assert_eq!(lvl, i + 1, "{}: levels must run from 1 without gaps", LEVEL_TABLE);
+        clvls.push(clvl.to_string());
+    }
+    let out = format!(
+        "pub const MAX_LEVEL: u8 = {};\n\npub const CLVL_TABLE: [u16; {}] = [{}];\n",
+        clvls.len(), clvls.len(), clvls.join(", "));
+    write(Path::new(&env::var("OUT_DIR").unwrap()).join("clvl_table.rs"), out).expect("writing the character level table");
+}
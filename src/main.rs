@@ -1,6 +1,7 @@
 pub mod qual;
 pub mod prog;
 pub mod statline;
+pub mod recipes;
 use std::collections::HashSet;
 use std::error;
 use std::time::Instant;
@@ -8,39 +9,144 @@ use std::fs::read;
 use std::fs::write;
 use std::fmt;
 use std::cmp;
-use bincode;
 use prog::Finisher;
 use serde::{Serialize, Deserialize};
 use std::io::BufReader;
 use std::fs::File;
-use serde_json;
+use std::path::Path;
 
 use crate::qual::DPCache;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Statline {
     time: u8,
     cp: u16,
     cms: u16,
     ctrl: u16,
+    // item to look the recipe up by in the recipe data; replaces rlvl through qmod
+    #[serde(default)]
+    item: Option<String>,
+    // progress, quality and durability factors to build the rlvl's recipe from; replaces prog through qmod
+    #[serde(default)]
+    factors: Option<recipes::Factors>,
+    #[serde(default)]
     rlvl: u16,
+    #[serde(default)]
     dur: u8,
+    #[serde(default)]
     prog: u32,
+    #[serde(default)]
     qual: u32,
-    has: bool
+    has: bool,
+    #[serde(default)]
+    expert: bool,
+    lvl: u8,
+    // recipe level table dividers and modifiers; unset, they come from the recipe level table
+    #[serde(default)]
+    pdiv: Option<u16>,
+    #[serde(default)]
+    qdiv: Option<u16>,
+    #[serde(default)]
+    pmod: Option<u16>,
+    #[serde(default)]
+    qmod: Option<u16>
+}
+
+fn default_opener_length() -> usize {
+    9
+}
+
+fn default_max_dense_mib() -> u64 {
+    4096
+}
+
+fn default_tricks() -> u8 {
+    qual::TRICKS_WITHOUT_TIME
+}
+
+fn default_data_dir() -> String {
+    "data".to_string()
 }
 
 impl Statline {
-    fn load(filename: &String) -> Result<Statline, Box<dyn error::Error>> {
-        let f = File::open(filename);
-        match f {
-            Ok(res) => {
-                match serde_json::from_reader(BufReader::new(res)) {
-                    Ok(res) => {Ok(res)}
-                    Err(err) => {Err(Box::new(err))}
-                }
+    fn load(filename: &String, data_dir: &str) -> Result<Statline, Box<dyn error::Error>> {
+        let f = File::open(filename)?;
+        let mut statline: Statline = serde_json::from_reader(BufReader::new(f))?;
+        if !(1..=statline::MAX_LEVEL).contains(&statline.lvl) {
+            return Err(format!("lvl {} is outside the character level table, 1 to {}", statline.lvl, statline::MAX_LEVEL).into())
+        }
+        statline.fill_recipe(Path::new(data_dir))?;
+        if statline.prog == 0 || statline.dur == 0 {
+            return Err("recipe file needs an item, the recipe's rlvl and factors, or its rlvl, prog, qual and dur".into())
+        }
+        Ok(statline)
+    }
+
+    /// Fills in the item's or the factors' recipe, and any dividers and modifiers left unset, from
+    /// the recipe data.
+    fn fill_recipe(&mut self, data_dir: &Path) -> Result<(), Box<dyn error::Error>> {
+        let unset = self.pdiv.is_none() || self.qdiv.is_none() || self.pmod.is_none() || self.qmod.is_none();
+        let lookup = self.item.is_some() || self.factors.is_some();
+        if !lookup && !unset {
+            return Ok(())
+        }
+        let db = match recipes::RecipeDb::load(data_dir) {
+            Ok(db) => db,
+            Err(err) if !lookup => {
+                println!("No recipe data ({}), assuming level 90 dividers and modifiers", err);
+                return Ok(())
             },
-            Err(err) => {Err(Box::new(err))}
+            Err(err) => return Err(err)
+        };
+        let entry = if let Some(name) = &self.item {
+            Some(db.by_name(name).ok_or_else(|| format!("no recipe for item {}", name))?.clone())
+        } else if let Some(factors) = self.factors {
+            Some(db.by_factors(self.rlvl, factors, self.expert).ok_or_else(|| format!("no recipe level {}", self.rlvl))?)
+        } else {
+            None
+        };
+        if let Some(entry) = entry {
+            let recipe = &entry.recipe;
+            self.expert |= entry.expert;
+            self.rlvl = recipe.rlvl;
+            self.prog = recipe.prog;
+            self.qual = recipe.reqqual.unwrap_or(recipe.qual);
+            self.dur = recipe.dur;
+            self.pdiv = Some(recipe.pdiv);
+            self.qdiv = Some(recipe.qdiv);
+            self.pmod = Some(recipe.pmod);
+            self.qmod = Some(recipe.qmod);
+        } else if let Some(level) = db.level(self.rlvl) {
+            self.pdiv = self.pdiv.or(Some(level.pdiv));
+            self.qdiv = self.qdiv.or(Some(level.qdiv));
+            self.pmod = self.pmod.or(Some(level.pmod));
+            self.qmod = self.qmod.or(Some(level.qmod));
+        }
+        Ok(())
+    }
+
+    fn recipe(&self) -> statline::Recipe {
+        // recipes from rlvl 580 on are the level 90 ones with progress and quality modifiers
+        let modified = self.rlvl >= 580;
+        statline::Recipe {
+            rlvl: self.rlvl,
+            prog: self.prog,
+            qual: self.qual,
+            dur: self.dur,
+            pdiv: self.pdiv.unwrap_or(130),
+            qdiv: self.qdiv.unwrap_or(115),
+            pmod: self.pmod.unwrap_or(if modified {80} else {100}),
+            qmod: self.qmod.unwrap_or(if modified {70} else {100}),
+            reqqual: None
+        }
+    }
+
+    fn stats(&self) -> statline::CrafterStats {
+        statline::CrafterStats {
+            lvl: self.lvl,
+            cp: self.cp,
+            cms: self.cms,
+            ctrl: self.ctrl
         }
     }
 }
@@ -59,61 +165,227 @@ struct Options {
     outcache: String,
     recipe_file: String,
     check_time: bool,
+    #[serde(default)]
+    conditions: bool,
+    #[serde(default)]
+    iterative: bool,
+    #[serde(default)]
+    dense: bool,
+    // most of the dense store to allocate, in MiB; states past it are hashed
+    #[serde(default = "default_max_dense_mib")]
+    max_dense_mib: u64,
+    #[serde(default)]
+    target: bool,
+    #[serde(default)]
+    assume_success: bool,
+    // 0 to 1: how much of the recipe's quality a failed craft is worth risking; unset never risks failing
+    #[serde(default)]
+    risk_tolerance: Option<f64>,
+    // Tricks of the Trade uses allowed without check_time, below 120; each one more multiplies the quality states
+    #[serde(default = "default_tricks")]
+    tricks_without_time: u8,
+    // let finishers use a Waste Not cast in the quality phase; roughly doubles the quality states
+    #[serde(default)]
+    carry_waste_not: bool,
+    // let finishers use Veneration left running by the opener or the quality phase
+    #[serde(default)]
+    carry_veneration: bool,
+    #[serde(default = "default_opener_length")]
+    opener_length: usize,
+    // most generated openers of each length to try, the ones leaving the most resources first; unset tries every
+    // opener nothing dominates
+    #[serde(default)]
+    max_openers_per_length: Option<usize>,
+    // openers to try instead of generating them
+    #[serde(default)]
+    openers: Vec<String>,
+    // directory holding the recipe data CSVs
+    #[serde(default = "default_data_dir")]
+    data_dir: String,
+    // food and medicine to compare in recipe mode and to pick from in gearset mode
+    #[serde(default)]
+    consumables: Vec<statline::Consumable>,
     bounds: Bounds
 }
 
-const LV_90_PROG_DIV: f64 = 130.;
-const LV_90_QUAL_DIV: f64 = 115.;
-const LV_90_PROG_MUL: f64 = 80.;
-const LV_90_QUAL_MUL: f64 = 70.;
+impl Options {
+    /// How far to search openers, and how many of each length to try.
+    fn opener_limits(&self) -> prog::OpenerLimits {
+        prog::OpenerLimits {max_len: self.opener_length, max_per_length: self.max_openers_per_length}
+    }
+
+    /// Buffs finishers may take over from the quality phase.
+    fn carried(&self) -> Vec<qual::Carry> {
+        [(self.carry_waste_not, qual::Carry::WasteNot), (self.carry_veneration, qual::Carry::Veneration)]
+            .into_iter().filter(|(on, _)| *on).map(|(_, carry)| carry).collect()
+    }
+}
 
-fn convert(recipe: &Statline, pst: &prog::State, finisher: &Finisher, prog_unit: u16) -> Option<(qual::State, bool)> {
+fn convert(recipe: &Statline, pst: &prog::State, finisher: &Finisher, prog_unit: u16, tricks: u8) -> Option<qual::State> {
     // Converts a prog state to a qual state if possible. If recipe would fail, returns None
     //assert!(pst.prog as u32 * (prog_unit as u32) < recipe.prog * 10, "Opener should not finish craft");
     if (pst.progress as u32 + finisher.progress as u32) * (prog_unit as u32) < recipe.prog * 10 {
         // Check that finisher finishes craft
         return None
     }
+    // the quality solver plays Reflect, so it gets back what the opener set aside for it
+    let (reflect_cp, reflect_durability) = if pst.reflect {(prog::REFLECT_CP, prog::REFLECT_DURABILITY)} else {(0, 0)};
     // check that there are resources remaining
-    if recipe.cp < pst.cp + finisher.cp || 
+    if pst.cp + reflect_cp < finisher.cp || 
         recipe.dur < pst.durability || 
         recipe.time < pst.time + finisher.time || 
         (!recipe.has && (pst.heart_and_soul || finisher.heart_and_soul)) ||
-        (pst.heart_and_soul && finisher.heart_and_soul) {
+        (pst.heart_and_soul && finisher.heart_and_soul) ||
+        (pst.trained_perfection != qual::Perfection::Available && finisher.trained_perfection) {
         return None
     }
 
-    Some((qual::State {
+    Some(qual::State {
         time: recipe.time - pst.time - finisher.time,
-        cp: pst.cp - finisher.cp,
+        cp: pst.cp + reflect_cp - finisher.cp,
         inner_quiet: pst.inner_quiet,
-        durability: pst.durability,
+        durability: pst.durability + reflect_durability,
         manipulation: pst.manipulation,
         waste_not: pst.waste_not,
         innovation: 0,
         great_strides: 0,
-        min_durability: finisher.durability - 1,
-        trained_perfection: 0,
-        heart_and_soul: recipe.has && !pst.heart_and_soul && !finisher.heart_and_soul
-    }, pst.reflect))
+        min_durability: finisher.durability,
+        trained_perfection: if finisher.trained_perfection {qual::Perfection::Used} else {pst.trained_perfection},
+        heart_and_soul: recipe.has && !pst.heart_and_soul && !finisher.heart_and_soul,
+        condition: qual::Condition::Normal,
+        combo: qual::Combo::None,
+        // an empty opener leaves the craft's first step to the quality phase
+        first_step: pst.reflect || pst.time == 0,
+        quick_innovation: recipe.has,
+        expedience: false,
+        // only a finisher taking Veneration over needs to know how long the opener's lasts
+        veneration: if finisher.carry == qual::Carry::Veneration {pst.veneration} else {0},
+        muscle_memory: 0,
+        progress: 0,
+        final_appraisal: 0,
+        carry: finisher.carry,
+        tricks
+    })
 }
 
-pub struct Rotation<'a> {
-    opener: &'a str,
-    extra: char,
-    finisher: &'a Finisher<'a>
+pub struct Rotation {
+    opener: String,
+    finisher: Finisher
 }
 
-struct SimResult<'a> {
+struct SimResult {
     best_qual: u32,
     best_time: u8,
-    best_rot: Rotation<'a>,
+    best_slack: (u16, u8, u8),
+    best_rot: Rotation,
     best_qst: qual::State
 }
 
-fn check_recipe<'a>(cache: &mut DPCache, recipe: &mut Statline, options: &Options) -> SimResult<'a> {
-    let prog_unit: u16 = ((recipe.cms as f64 * 10. / LV_90_PROG_DIV + 2.) * if recipe.rlvl >= 580 {LV_90_PROG_MUL} else {100.} / 100.).floor() as u16;
-    let qual_unit: u16 = ((recipe.ctrl as f64 * 10. / LV_90_QUAL_DIV + 35.) * if recipe.rlvl >= 580 {LV_90_QUAL_MUL} else {100.} / 100.).floor() as u16;
+/// Progress and quality per 100% efficiency.
+fn units(recipe: &Statline) -> (u16, u16) {
+    let info = statline::combine_info(&recipe.recipe(), &recipe.stats());
+    (info.p100, info.q100)
+}
+
+/// Progress the unified solver has to reach, if it fits in its state.
+fn progress_needed(recipe: &Statline) -> Option<u16> {
+    u16::try_from(recipe.prog).ok()
+}
+
+fn new_cache(recipe: &Statline, options: &Options, max_cp: u16, max_progress: u16) -> Result<DPCache, String> {
+    let conditions = if options.conditions {
+        qual::ConditionTable::for_recipe(recipe.expert)
+    } else {
+        qual::ConditionTable::NormalOnly
+    };
+    if options.tricks_without_time >= 120 {
+        return Err(format!("tricks_without_time is {}, it must be below 120", options.tricks_without_time))
+    }
+    let mut cache = DPCache::new(recipe.dur / 5, max_cp, options.check_time, conditions, max_progress);
+    cache.tricks = options.tricks_without_time;
+    // stored with the cache, since it changes the cached values
+    cache.assume_success = options.assume_success;
+    // a failed craft counts as losing the untolerated share of the recipe's quality
+    let (prog_unit, qual_unit) = units(recipe);
+    cache.prog_unit = prog_unit;
+    cache.failure_penalty = options.risk_tolerance
+        .map(|t| (1. - t.clamp(0., 1.)) * recipe.qual as f64 * qual::UNIT as f64 / qual_unit as f64);
+    cache.set_available(qual::action_mask(recipe.lvl, max_progress > 0, recipe.has));
+    if options.dense {
+        // the dense store's layout depends on the available actions
+        let slots = qual::DenseStore::slots(&cache, &options.carried());
+        println!("Dense store: {} slots ({} MiB), allocating up to {} MiB", slots, slots.saturating_mul(8) >> 20, options.max_dense_mib);
+        cache.make_dense(&options.carried(), options.max_dense_mib << 17)?;
+    }
+    Ok(cache)
+}
+
+/// State the unified solver starts `recipe` from.
+fn unified_start(recipe: &Statline, tricks: u8) -> qual::State {
+    qual::State {
+        time: recipe.time,
+        inner_quiet: 0,
+        cp: recipe.cp,
+        durability: recipe.dur / 5,
+        manipulation: 0,
+        waste_not: 0,
+        innovation: 0,
+        great_strides: 0,
+        min_durability: 0,
+        trained_perfection: qual::Perfection::Available,
+        heart_and_soul: recipe.has,
+        condition: qual::Condition::Normal,
+        combo: qual::Combo::None,
+        first_step: true,
+        quick_innovation: recipe.has,
+        expedience: false,
+        veneration: 0,
+        muscle_memory: 0,
+        progress: progress_needed(recipe).unwrap_or(u16::MAX),
+        final_appraisal: 0,
+        carry: qual::Carry::None,
+        tricks
+    }
+}
+
+/// Progress state an opener for `recipe` starts from.
+fn opener_start(recipe: &Statline, progress_limit: u16) -> prog::State {
+    prog::State {
+        time: 0,
+        inner_quiet: 0,
+        cp: recipe.cp,
+        durability: recipe.dur / 5,
+        max_durability: recipe.dur / 5,
+        manipulation: 0,
+        waste_not: 0,
+        veneration: 0,
+        muscle_memory: 0,
+        heart_and_soul: false,
+        reflect: false,
+        progress: 0,
+        level: recipe.lvl,
+        quality: 0,
+        final_appraisal: 0,
+        progress_limit,
+        trained_perfection: qual::Perfection::Available
+    }
+}
+
+/// The user-supplied `openers` that can be played on `recipe` without completing it, reporting the others.
+fn valid_openers(recipe: &Statline, progress_limit: u16, openers: &[String]) -> Vec<String> {
+    openers.iter().filter(|opener| {
+        let mut st = opener_start(recipe, progress_limit);
+        match st.validate_opener(opener) {
+            Err(err) => println!("Skipping opener {}: {}", opener, err),
+            Ok(()) if st.progress >= progress_limit => println!("Skipping opener {}: completes the craft", opener),
+            Ok(()) => return true
+        }
+        false
+    }).cloned().collect()
+}
+
+fn check_recipe(cache: &mut DPCache, recipe: &mut Statline, options: &Options) -> Result<SimResult, String> {
+    let (prog_unit, qual_unit) = units(recipe);
     println!("Prog/100: {}", prog_unit);
     println!("Qual/100: {}", qual_unit);
     let mut min = if options.check_time {0} else {recipe.time - 1};
@@ -121,66 +393,78 @@ fn check_recipe<'a>(cache: &mut DPCache, recipe: &mut Statline, options: &Option
     let mut max = recipe.time;
     
     let mut best_qual = 0;
+    let mut best_slack = (0, 0, 0);
     let mut best_rot: Option<Rotation> = None;
     let mut best_qst: Option<qual::State> = None;
+    let finishers = prog::finishers(recipe.lvl, &options.carried());
+    let needed = (recipe.prog * 10).div_ceil(prog_unit as u32) as u16;
+    let thresholds: Vec<u16> = finishers.iter().map(|f| needed.saturating_sub(f.progress)).collect();
+    let openers = if options.openers.is_empty() {
+        prog::generate_openers(recipe.cp, recipe.dur / 5, recipe.lvl, recipe.has, options.opener_limits(), needed, &thresholds)
+    } else {
+        valid_openers(recipe, needed, &options.openers)
+    };
+    println!("Openers: {}", openers.len());
     while min <= max {
-        dbg!(min, t, max);
         recipe.time = t;
         best_qual = 0;
+        best_slack = (0, 0, 0);
         best_rot = None;
         best_qst = None;
-        for opener in prog::OPENERS {
-            for extra in " bcf".chars() {
-                let mut st = prog::State {
-                    time: 0,
-                    inner_quiet: 0,
-                    cp: recipe.cp,
-                    durability: recipe.dur / 5,
-                    manipulation: 0,
-                    waste_not: 0,
-                    veneration: 0,
-                    muscle_memory: 0,
-                    heart_and_soul: recipe.has,
-                    reflect: false,
-                    progress: 0
-                };
-                st.apply_opener(opener, extra);
-                if st.progress as u32 * prog_unit as u32 >= recipe.prog * 10 {
+        let mut solved: Vec<(qual::State, u16)> = Vec::new();
+        for opener in &openers {
+            let mut st = opener_start(recipe, needed);
+            if st.apply_opener(opener).is_err() || st.progress as u32 * prog_unit as u32 >= recipe.prog * 10 {
+                continue;
+            }
+            let good_finishers: Vec<&Finisher> = finishers.iter().filter(|f| 
+                (f.progress + st.progress) as u32 * (prog_unit as u32) >= recipe.prog * 10).collect();
+            'outer: for finisher in &finishers {
+                for fin2 in &good_finishers {
+                    if fin2.beats(finisher) && *fin2 != finisher {
+                        continue 'outer;
+                    }
+                }
+                let Some(qst) = convert(recipe, &st, finisher, prog_unit, cache.tricks) else {continue};
+                // a state with less of everything, after an opener and finisher with no more quality, can't do better
+                let progress_quality = st.quality + finisher.quality;
+                if solved.iter().any(|(other, quality)| other.dominates(&qst) && *quality >= progress_quality) {
                     continue;
                 }
-                let good_finishers: Vec<&&Finisher> = prog::FINISHERS.iter().filter(|f| 
-                    (f.progress + st.progress) as u32 * (prog_unit as u32) >= recipe.prog * 10).collect();
-                'outer: for finisher in prog::FINISHERS {
-                    for fin2 in &good_finishers {
-                        if fin2.beats(finisher) && **fin2 != finisher {
-                            continue 'outer;
-                        }
-                    }
-                    let st = st.clone();
-                    let res = convert(&recipe, &st, finisher, prog_unit);
-                    let qst: qual::State;
-                    let bonus_qual;
-                    match res {
-                        Some((st, reflect)) => {qst = st; bonus_qual = if reflect {qual::UNIT} else {0};}
-                        None => continue
-                    }
-                    dbg!(format!("{}{} {}", opener, extra, finisher.description));
-                    dbg!((st.progress as u32 + finisher.progress as u32) * prog_unit as u32);
-                    let (q, _method, _next) = qual::unpack_method(cache.unwrapped_query(&qst));
-                    let q = (q + bonus_qual) as u32 * qual_unit as u32 / qual::UNIT as u32;
-                    if q > best_qual {
-                        best_qual = q;
-                        best_qst = Some(qst);
-                        best_rot = Some(Rotation {
-                            opener,
-                            extra,
-                            finisher: &finisher
-                        });
+                solved.retain(|(other, quality)| !(qst.dominates(other) && progress_quality >= *quality));
+                solved.push((qst, progress_quality));
+                let (q, _method, _next) = qual::unpack_method(cache.unwrapped_query(&qst));
+                let opener_q = progress_quality as u32 * qual::UNIT as u32 / 100;
+                let mut q = (q as u32 + opener_q) * qual_unit as u32 / qual::UNIT as u32;
+                let mut qst = qst;
+                let mut slack = (0, 0, 0);
+                if options.target && q >= recipe.qual {
+                    // reach the recipe's quality with the most CP, then durability, then time to spare
+                    // the opener's and finisher's quality counts towards the target, so the quality phase needs only the rest
+                    let needed = (recipe.qual * qual::UNIT as u32).div_ceil(qual_unit as u32).saturating_sub(opener_q);
+                    if let Some(reduced) = cache.target_state(&qst, needed.min(u16::MAX as u32) as u16) {
+                        slack = (qst.cp - reduced.cp, qst.durability - reduced.durability, qst.time - reduced.time);
+                        qst = reduced;
+                        let (rq, _method, _next) = qual::unpack_method(cache.unwrapped_query(&qst));
+                        q = (rq as u32 + opener_q) * qual_unit as u32 / qual::UNIT as u32;
                     }
                 }
+                let better = if options.target {
+                    (cmp::min(q, recipe.qual), slack) > (cmp::min(best_qual, recipe.qual), best_slack)
+                } else {
+                    q > best_qual
+                };
+                if better {
+                    best_qual = q;
+                    best_slack = slack;
+                    best_qst = Some(qst);
+                    best_rot = Some(Rotation {
+                        opener: opener.clone(),
+                        finisher: finisher.clone()
+                    });
+                }
             }
         }
-        dbg!(t, best_qual);
         if max == min {break;}
         if best_qual >= recipe.qual {
             max = t;
@@ -189,18 +473,21 @@ fn check_recipe<'a>(cache: &mut DPCache, recipe: &mut Statline, options: &Option
         }
         t = (max + min) / 2;
     }
-    SimResult {
+    let (Some(best_rot), Some(best_qst)) = (best_rot, best_qst) else {
+        return Err("no opener and finisher complete the craft".to_string())
+    };
+    Ok(SimResult {
         best_qual,
         best_time: t,
-        best_rot: best_rot.unwrap(),
-        best_qst: best_qst.unwrap()
-    }
+        best_slack,
+        best_rot,
+        best_qst
+    })
 }
 
 fn convert_char(c: char) -> (&'static str, i32) {
     match c {
         'M' => ("Muscle Memory", 3),
-        'R' => ("Reflect", 3),
         'm' => ("Manipulation", 2),
         'v' => ("Veneration", 2),
         '1' => ("Waste Not", 2),
@@ -211,11 +498,16 @@ fn convert_char(c: char) -> (&'static str, i32) {
         'f' => ("Observe", 3),
         'g' => ("Groundwork", 3),
         'i' => ("Heart and Soul", 3),
+        'd' => ("Delicate Synthesis", 3),
+        'a' => ("Final Appraisal", 2),
+        't' => ("Trained Perfection", 2),
+        'r' => ("Immaculate Mend", 2),
         _ => ("", 1)
     }
 }
 
 fn print_char(c: char) {
+    if c == 'R' {return;} // Reflect is printed with the quality rotation
     let (name, wait) = convert_char(c);
     println!("/ac \"{}\" <wait.{}>", name, wait);
     if c == 'f' {
@@ -235,7 +527,7 @@ struct Solution {
 
 impl Solution {
     fn beats(&self, other: &Self) -> bool {
-        self.cms <= other.cms && self.ctrl <= other.ctrl && self.cp <= other.cp && *self != *other && !(self.has && !other.has)
+        self.cms <= other.cms && self.ctrl <= other.ctrl && self.cp <= other.cp && *self != *other && (!self.has || other.has)
     }
 }
 
@@ -245,6 +537,64 @@ impl fmt::Display for Solution {
     }
 }
 
+/// Solves `recipe` again with each food, each medicine and each food and medicine pair, at NQ and HQ,
+/// and reports the quality against `base_qual`.
+fn compare_consumables(cache: &mut DPCache, recipe: &Statline, base_qual: u32, options: &Options) {
+    let mut results = Vec::new();
+    for buffs in statline::buff_sets(&options.consumables).into_iter().filter(|buffs| !buffs.is_empty()) {
+        let stats = recipe.stats().with(&buffs);
+        let buff = buffs.iter().map(|buff| buff.to_string()).collect::<Vec<_>>().join(" + ");
+        let mut buffed = Statline {cp: stats.cp, cms: stats.cms, ctrl: stats.ctrl, ..recipe.clone()};
+        // the failure penalty depends on the stats, so the cached values do too
+        let mut own;
+        let cache = if options.risk_tolerance.is_some() {
+            own = match new_cache(&buffed, options, buffed.cp, 0) {
+                Ok(cache) => cache,
+                Err(err) => {
+                    results.push((buff, stats, Err(err)));
+                    continue;
+                }
+            };
+            own.iterative = options.iterative;
+            &mut own
+        } else {
+            &mut *cache
+        };
+        let result = check_recipe(cache, &mut buffed, options);
+        results.push((buff, stats, result));
+    }
+    println!("Consumables:");
+    println!("{:<48} {}/{}/{} quality {}", "None", recipe.cms, recipe.ctrl, recipe.cp, base_qual);
+    for (buff, stats, result) in results {
+        match result {
+            Ok(SimResult {best_qual, best_time, ..}) =>
+                println!("{:<48} {}/{}/{} quality {} ({:+}) time {}", buff, stats.cms, stats.ctrl, stats.cp,
+                    best_qual, best_qual as i64 - base_qual as i64, best_time),
+            Err(err) => println!("{:<48} {}/{}/{} {}", buff, stats.cms, stats.ctrl, stats.cp, err)
+        }
+    }
+}
+
+/// Reports the cheapest food and medicine that lift the recipe file's stats to one of the gearset `solutions`.
+fn cheapest_consumables(solutions: &HashSet<Solution>, recipe: &Statline, consumables: &[statline::Consumable]) {
+    let base = recipe.stats();
+    let reaches = |stats: &statline::CrafterStats| solutions.iter()
+        .any(|sol| stats.cms >= sol.cms && stats.ctrl >= sol.ctrl && stats.cp >= sol.cp);
+    let cheapest = statline::buff_sets(consumables).into_iter()
+        .filter(|buffs| reaches(&base.with(buffs)))
+        .min_by_key(|buffs| buffs.iter().map(|buff| buff.cost()).sum::<u32>());
+    let stats = format!("{}/{}/{}", base.cms, base.ctrl, base.cp);
+    match cheapest {
+        None => println!("No consumables lift {} to a solution", stats),
+        Some(buffs) if buffs.is_empty() => println!("{} reaches a solution without consumables", stats),
+        Some(buffs) => {
+            let names: Vec<String> = buffs.iter().map(|buff| buff.to_string()).collect();
+            let cost: u32 = buffs.iter().map(|buff| buff.cost()).sum();
+            println!("Cheapest consumables for {}: {} (cost {})", stats, names.join(" + "), cost);
+        }
+    }
+}
+
 fn load_options() -> Result<Options, String> {
     File::open("options.json")
         .map_err(|err| err.to_string())
@@ -261,7 +611,31 @@ fn export_cache(outfile: &String, cache: &DPCache) -> Result<(), String> {
         })
 }
 
+/// `qualsim import <game csv dir> [data dir]`: rebuilds the recipe data from the game's tables.
+fn import(args: &[String]) {
+    let Some(game_dir) = args.first() else {
+        println!("Usage: qualsim import <directory with Item.csv, Recipe.csv and RecipeLevelTable.csv> [output directory]");
+        return
+    };
+    let out_dir = args.get(1).map_or("data", String::as_str);
+    match recipes::import(Path::new(game_dir), Path::new(out_dir)) {
+        Ok((items, distinct)) => {
+            match items {
+                Some(items) => println!("Wrote {} item recipes to {}", items, recipes::ITEM_RECIPES_FILE),
+                None => println!("No {}, keeping {}", recipes::ITEM_TABLE_FILE, recipes::ITEM_RECIPES_FILE)
+            }
+            println!("Wrote {} distinct recipes to {}", distinct, recipes::DISTINCT_RECIPES_FILE);
+        },
+        Err(err) => println!("Error importing recipes: {}", err)
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("import") {
+        import(&args[2..]);
+        return;
+    }
     let mut options = match load_options() {
         Ok(res) => res,
         Err(err) => {
@@ -273,7 +647,7 @@ fn main() {
 
     let start = Instant::now();
     println!("Cache loaded in +{}ms", start.elapsed().as_millis());
-    let mut recipe = match Statline::load(&options.recipe_file) {
+    let mut recipe = match Statline::load(&options.recipe_file, &options.data_dir) {
         Ok(res) => res,
         Err(err) => {
             println!("Error loading options file: {}", err);
@@ -281,7 +655,7 @@ fn main() {
         }
     };
 
-    if options.incache.len() > 0 {
+    if !options.incache.is_empty() {
         cache = match read(&options.incache).map_err(|err| err.to_string())
             .and_then(|res| bincode::deserialize(&res).map_err(|err| err.to_string())) {
                 Ok(cache) => cache,
@@ -291,114 +665,195 @@ fn main() {
                 }
             }
     } else {
-        cache = DPCache::new(recipe.dur, options.check_time)
+        let max_cp = if options.mode == "gearset" {
+            options.bounds.cp.1 + 15
+        } else if options.mode == "recipe" {
+            // room for the CP the consumables add
+            statline::buff_sets(&options.consumables).iter()
+                .map(|buffs| recipe.stats().with(buffs).cp).fold(recipe.cp, cmp::max)
+        } else {recipe.cp};
+        let max_progress = if options.mode == "unified" {
+            match progress_needed(&recipe) {
+                Some(progress) => progress,
+                None => {
+                    println!("Recipe progress is too large for the unified solver");
+                    return;
+                }
+            }
+        } else {0};
+        cache = match new_cache(&recipe, &options, max_cp, max_progress) {
+            Ok(cache) => cache,
+            Err(err) => {
+                println!("Error creating cache: {}", err);
+                return;
+            }
+        };
     }
+    cache.iterative = options.iterative;
 
     if options.mode == "recipe" {
-        let result = check_recipe(&mut cache, &mut recipe, &options);
-        let SimResult {best_rot, best_qst, best_qual, best_time} = result;
-        let finisher = format!("{}", best_rot.finisher.description);
+        let base = recipe.clone();
+        let result = match check_recipe(&mut cache, &mut recipe, &options) {
+            Ok(result) => result,
+            Err(err) => {
+                println!("No rotation found: {}", err);
+                return;
+            }
+        };
+        let SimResult {best_rot, best_qst, best_qual, best_time, best_slack} = result;
+        let finisher = best_rot.finisher.description.to_string();
+        let mut qst = best_qst;
+        if let Some((method, next)) = cache.first_step(&best_qst) {
+            qual::print_action(&qual::ACTIONS[method as usize]);
+            qst = next;
+        }
         for c in best_rot.opener.chars() {
             print_char(c);
         }
-        if best_rot.extra != ' ' {
-            print_char(best_rot.extra);
-        }
-        cache.print_macro(&best_qst);
+        cache.print_macro(&qst);
         for c in finisher.chars() {
             print_char(c);
         }
         println!("Best time: {}", best_time);
         println!("Quality: {}", best_qual);
+        let (_, _, completion) = qual::unpack_method(cache.unwrapped_query(&best_qst));
+        println!("Failure chance: {:.2}%", (1. - completion) * 100.);
+        if options.target {
+            println!("Spare CP: {}, durability: {}, time: {}", best_slack.0, best_slack.1 as u16 * 5, best_slack.2);
+        }
         cache.print_backtrace(&best_qst);
+        if !options.consumables.is_empty() {
+            compare_consumables(&mut cache, &base, best_qual, &options);
+        }
         //println!("hits: {}", cache.hits);
         //println!("items: {}", cache.items);
+    } else if options.mode == "unified" {
+        let (_, qual_unit) = units(&recipe);
+        let start = unified_start(&recipe, cache.tricks);
+        let Some(res) = cache.query(&start) else {
+            println!("No rotation completes the craft");
+            return;
+        };
+        let (q, _method, completion) = qual::unpack_method(res.get());
+        let unified_qual = q as u32 * qual_unit as u32 / qual::UNIT as u32;
+        cache.print_macro(&start);
+        println!("Quality: {}", unified_qual);
+        println!("Failure chance: {:.2}%", (1. - completion) * 100.);
+        cache.print_backtrace(&start);
+        let split = new_cache(&recipe, &options, recipe.cp, 0).and_then(|mut split| {
+            split.iterative = options.iterative;
+            check_recipe(&mut split, &mut recipe, &options)
+        });
+        match split {
+            Ok(SimResult {best_rot, best_qual, ..}) => {
+                println!("Split heuristic: {} with {} / {}", best_qual, best_rot.opener, best_rot.finisher.description);
+                println!("Unified gain: {}", unified_qual as i64 - best_qual as i64);
+            },
+            Err(err) => println!("Split heuristic: {}", err)
+        }
+    } else if options.mode == "frontier" {
+        if !options.check_time {
+            println!("Frontier mode needs check_time, to tell rotations apart by the time they take");
+            return;
+        }
+        let SimResult {best_rot, best_qst, ..} = match check_recipe(&mut cache, &mut recipe, &options) {
+            Ok(result) => result,
+            Err(err) => {
+                println!("No rotation found: {}", err);
+                return;
+            }
+        };
+        println!("Frontier after {} / {}", best_rot.opener, best_rot.finisher.description);
+        for point in cache.frontier(&best_qst) {
+            let names: Vec<&str> = cache.rotation(&point.start).iter()
+                .map(|(method, _, _)| qual::ACTION_NAMES[*method as usize]).collect();
+            println!("{:.4} CP {:3} T {:3} {}", point.quality as f64 / 400.0, point.cp, point.time, names.join(", "));
+        }
     } else if options.mode == "gearset" {
         if recipe.has { // Raise upper bound to allow specialist
             options.bounds.cms.1 += 20;
             options.bounds.ctrl.1 += 20;
             options.bounds.cp.1 += 15;
         }
-        let min_prog_unit: u16 = ((options.bounds.cms.0 as f64 * 10. / LV_90_PROG_DIV + 2.) * if recipe.rlvl >= 580 {LV_90_PROG_MUL} else {100.} / 100.).floor() as u16;
-        let max_prog_unit: u16 = ((options.bounds.cms.1 as f64 * 10. / LV_90_PROG_DIV + 2.) * if recipe.rlvl >= 580 {LV_90_PROG_MUL} else {100.} / 100.).floor() as u16;
-        let min_qual_unit: u16 = ((options.bounds.ctrl.0 as f64 * 10. / LV_90_QUAL_DIV + 35.) * if recipe.rlvl >= 580 {LV_90_QUAL_MUL} else {100.} / 100.).floor() as u16;
-        //let max_qual_unit: u16 = ((options.bounds.ctrl.1 as f64 * 10. / LV_90_QUAL_DIV + 35.) * if recipe.rlvl >= 580 {LV_90_QUAL_MUL} else {100.} / 100.).floor() as u16;
-        //dbg!(min_prog_unit, min_qual_unit);
+        let stat_recipe = recipe.recipe();
+        let at_bound = |cms, ctrl| statline::combine_info(&stat_recipe, &statline::CrafterStats {cms, ctrl, ..recipe.stats()});
+        let min_prog_unit = at_bound(options.bounds.cms.0, options.bounds.ctrl.0).p100;
+        let max_prog_unit = at_bound(options.bounds.cms.1, options.bounds.ctrl.1).p100;
+        let min_qual_unit = at_bound(options.bounds.cms.0, options.bounds.ctrl.0).q100;
         let mut solutions: HashSet<Solution> = HashSet::new();
+        let finishers = prog::finishers(recipe.lvl, &options.carried());
+        // generated once for the largest budget and for the progress needed at either end of the bounds
+        let needed = [min_prog_unit, max_prog_unit].map(|pu| (recipe.prog * 10).div_ceil(pu as u32) as u16);
+        let thresholds: Vec<u16> = finishers.iter().flat_map(|f| needed.map(|n| n.saturating_sub(f.progress))).collect();
+        let openers = prog::generate_openers(options.bounds.cp.1, recipe.dur / 5, recipe.lvl, recipe.has, options.opener_limits(), needed[0], &thresholds);
         for target_cp in options.bounds.cp.0..=options.bounds.cp.1 {
-            for opener in prog::OPENERS {
-                for extra in " bcf".chars() {
-                    for has in 0..=recipe.has as u8 {
-                        let mut st = prog::State {
-                            time: 0,
-                            inner_quiet: 0,
-                            cp: target_cp,
-                            durability: recipe.dur / 5,
-                            manipulation: 0,
-                            waste_not: 0,
-                            veneration: 0,
-                            muscle_memory: 0,
-                            heart_and_soul: false,
-                            reflect: false,
-                            progress: 0
-                        };
-                        st.apply_opener(opener, extra);
-                        if st.progress as u32 * min_prog_unit as u32 >= recipe.prog * 10 {
-                            continue;
-                        }
-                        let opener_prog = st.progress;
-                        let good_finishers: Vec<&&Finisher> = prog::FINISHERS.iter().filter(|f| 
-                            (f.progress + st.progress) as u32 * (max_prog_unit as u32) >= recipe.prog * 10).collect();
-                        'finLoop: for finisher in good_finishers {
-                            let st = st.clone();
-                            let res = convert(&recipe, &st, finisher, max_prog_unit);
-                            let mut qst: qual::State;
-                            let bonus_qual;
-                            match res {
-                                Some((st, reflect)) => {qst = st; bonus_qual = if reflect {qual::UNIT} else {0};}
-                                None => continue
-                            }
-                            if recipe.has && has == 0 { // Special check to handle recipe HaS being weird
-                                if qst.heart_and_soul {
-                                    qst.heart_and_soul = false;
-                                } else {
-                                    continue;
-                                }
-                            }
-                            //dbg!(format!("{}{} {}", opener, extra, finisher.desc));
-                            let (q, _method, _next) = qual::unpack_method(cache.unwrapped_query(&qst));
-                            let q = (q + bonus_qual) as f64 / qual::UNIT as f64;
-                            let p = (finisher.progress + opener_prog) as f64 / 10.;
-                            let min_cms: u16 = (13. * ((recipe.prog as f64 / p).ceil() * 1.25 - 2.)).ceil() as u16;
-                            let min_ctrl: u16 = (11.5 * ((recipe.qual as f64 / q).ceil() * 10. / 7. - 35.)).ceil() as u16;
-                            //dbg!(min_cms);
-                            if min_cms > options.bounds.cms.1 || min_ctrl > options.bounds.ctrl.1 {
-                                continue;
-                            }
-                            let pu = (recipe.prog as f64 / p as f64).ceil();
-                            let qu = (recipe.qual as f64 / q as f64).ceil();
-                            if pu + 2. < min_prog_unit as f64 || qu + 2. < min_qual_unit as f64{
-                                //dbg!(pu, qu);
+            for opener in &openers {
+                for has in 0..=recipe.has as u8 {
+                    let mut st = prog::State {
+                        time: 0,
+                        inner_quiet: 0,
+                        cp: target_cp,
+                        durability: recipe.dur / 5,
+                        max_durability: recipe.dur / 5,
+                        manipulation: 0,
+                        waste_not: 0,
+                        veneration: 0,
+                        muscle_memory: 0,
+                        heart_and_soul: false,
+                        reflect: false,
+                        progress: 0,
+                        level: recipe.lvl,
+                        quality: 0,
+                        final_appraisal: 0,
+                        progress_limit: needed[1],
+                        trained_perfection: qual::Perfection::Available
+                    };
+                    // openers are generated for the largest CP budget, so some don't fit the smaller ones
+                    if st.apply_opener(opener).is_err() || st.progress as u32 * min_prog_unit as u32 >= recipe.prog * 10 {
+                        continue;
+                    }
+                    let opener_prog = st.progress;
+                    let good_finishers: Vec<&Finisher> = finishers.iter().filter(|f| 
+                        (f.progress + st.progress) as u32 * (max_prog_unit as u32) >= recipe.prog * 10).collect();
+                    'finLoop: for finisher in good_finishers {
+                        let Some(mut qst) = convert(&recipe, &st, finisher, max_prog_unit, cache.tricks) else {continue};
+                        if recipe.has && has == 0 { // Special check to handle recipe HaS being weird
+                            if qst.heart_and_soul {
+                                qst.heart_and_soul = false;
+                                qst.quick_innovation = false;
+                            } else {
                                 continue;
                             }
-                            let new_sol = Solution  {
-                                cms: cmp::max(min_cms, options.bounds.cms.0), 
-                                ctrl: cmp::max(min_ctrl, options.bounds.ctrl.0),
-                                cp: target_cp,
-                                has: (has > 0) && !cache.check_endstate(&qst).heart_and_soul
-                            };
-                            solutions.retain(|sol| {
-                                !new_sol.beats(sol)
-                            });
-                            for sol in &solutions {
-                                if sol.beats(&new_sol) {
-                                    continue 'finLoop;
-                                }
+                        }
+                        let (q, _method, _next) = qual::unpack_method(cache.unwrapped_query(&qst));
+                        let q = q as f64 / qual::UNIT as f64 + (st.quality + finisher.quality) as f64 / 100.;
+                        let p = (finisher.progress + opener_prog) as f64 / 10.;
+                        let pu = (recipe.prog as f64 / p).ceil();
+                        let qu = (recipe.qual as f64 / q).ceil();
+                        let min_cms = statline::min_craftsmanship(&stat_recipe, recipe.lvl, pu as u16);
+                        let min_ctrl = statline::min_control(&stat_recipe, recipe.lvl, qu as u16);
+                        if min_cms > options.bounds.cms.1 || min_ctrl > options.bounds.ctrl.1 {
+                            continue;
+                        }
+                        if pu + 2. < min_prog_unit as f64 || qu + 2. < min_qual_unit as f64{
+                            continue;
+                        }
+                        let new_sol = Solution  {
+                            cms: cmp::max(min_cms, options.bounds.cms.0), 
+                            ctrl: cmp::max(min_ctrl, options.bounds.ctrl.0),
+                            cp: target_cp,
+                            has: (has > 0) && !cache.check_endstate(&qst).heart_and_soul
+                        };
+                        solutions.retain(|sol| {
+                            !new_sol.beats(sol)
+                        });
+                        for sol in &solutions {
+                            if sol.beats(&new_sol) {
+                                continue 'finLoop;
                             }
-                            //dbg!(cache.check_endstate(&qst));
-                            //println!("{}", &new_sol);
-                            solutions.insert(new_sol);
                         }
+                        //println!("{}", &new_sol);
+                        solutions.insert(new_sol);
                     }
                 }
             }
@@ -406,9 +861,12 @@ fn main() {
         for sol in &solutions {
             println!("{}", sol);
         }
+        if !options.consumables.is_empty() {
+            cheapest_consumables(&solutions, &recipe, &options.consumables);
+        }
     }
     println!("Main operation completed by +{}ms", start.elapsed().as_millis());
-    if options.outcache.len() > 0 {
+    if !options.outcache.is_empty() {
         match export_cache(&options.outcache, &cache) {
             Ok(_) => {},
             Err(err) => {
@@ -418,3 +876,31 @@ fn main() {
     }
     println!("Cache write finished by +{}ms", start.elapsed().as_millis());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_recipe() -> (Statline, Options) {
+        let recipe = serde_json::from_str(r#"{"time": 30, "cp": 180, "cms": 120, "ctrl": 110, "rlvl": 20, "dur": 40,
+            "prog": 90, "qual": 1000, "has": false, "lvl": 20, "pdiv": 50, "qdiv": 30}"#).unwrap();
+        let options = serde_json::from_str(r#"{"mode": "unified", "incache": "", "outcache": "", "recipe_file": "",
+            "check_time": false, "bounds": {"cms": [0, 0], "ctrl": [0, 0], "cp": [0, 0]}}"#).unwrap();
+        (recipe, options)
+    }
+
+    #[test]
+    fn unified_at_least_split() {
+        let (mut recipe, options) = small_recipe();
+        let (_, qual_unit) = units(&recipe);
+        let mut unified = new_cache(&recipe, &options, recipe.cp, progress_needed(&recipe).unwrap()).unwrap();
+        let start = unified_start(&recipe, unified.tricks);
+        let (q, _, completion) = qual::unpack_method(unified.query(&start).unwrap().get());
+        let unified_qual = q as u32 * qual_unit as u32 / qual::UNIT as u32;
+        let mut split = new_cache(&recipe, &options, recipe.cp, 0).unwrap();
+        let split_qual = check_recipe(&mut split, &mut recipe, &options).unwrap().best_qual;
+        // the unified solver searches every interleaving of progress and quality actions, so it does no worse
+        assert_eq!(completion, 1.);
+        assert!(unified_qual >= split_qual, "unified {} below split {}", unified_qual, split_qual);
+    }
+}
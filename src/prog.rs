@@ -1,16 +1,28 @@
+use std::cmp::{min, Reverse};
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use crate::qual::{self, Carry, Perfection};
+
 #[derive(Debug, Clone)]
 pub struct State {
     pub time: u8, // 0-89, 7 bits
     pub inner_quiet: u8, // 0-10, 4 bits
     pub cp: u16, // 0-699, 10 bits
     pub durability: u8, // 0-16, 5 bits
+    pub max_durability: u8,
     pub manipulation: u8, // 0-8, 4 bits
     pub waste_not: u8, // 0-8, 4 bits
     pub veneration: u8,
     pub muscle_memory: u8, 
     pub heart_and_soul: bool,
     pub reflect: bool,
-    pub progress: u16
+    pub progress: u16,
+    pub level: u8,
+    pub quality: u16, // percent of base quality, from Delicate Synthesis
+    pub final_appraisal: u8,
+    pub progress_limit: u16, // progress that completes the craft, which Final Appraisal stops short of
+    pub trained_perfection: Perfection // once per craft, shared with the quality phase
 }
 
 
@@ -22,14 +34,20 @@ mod actions {
         Manipulation,
         WasteNot,
         Veneration,
-        MuscleMemory
+        MuscleMemory,
+        FinalAppraisal,
+        TrainedPerfection,
+        ImmaculateMend
     }
     pub struct Action {
         pub progress: u16, // Efficiency x10
         pub durability: u8, // 5dur = 1
         pub cp: u16,
         pub status: Status,
-        pub duration: u8
+        pub duration: u8,
+        pub quality: u16, // efficiency in percent
+        pub halved_when_short: bool, // half efficiency with less durability left than it costs
+        pub blocked_by_waste_not: bool
     }
 
     impl Action {
@@ -39,47 +57,159 @@ mod actions {
                 durability,
                 cp,
                 status,
-                duration
+                duration,
+                quality: 0,
+                halved_when_short: false,
+                blocked_by_waste_not: false
             }
         }
+
+        pub const fn touch(self, quality: u16) -> Action {
+            Action { quality, ..self }
+        }
+
+        pub const fn halved_when_short(self) -> Action {
+            Action { halved_when_short: true, ..self }
+        }
+
+        pub const fn blocked_by_waste_not(self) -> Action {
+            Action { blocked_by_waste_not: true, ..self }
+        }
     }
 
 	pub const BASIC: Action = Action::new(12, 2, 0, Status::None, 0 );
+	pub const BASIC_NOVICE: Action = Action::new(10, 2, 0, Status::None, 0 ); // below Basic Synthesis Mastery
 	pub const CAREFUL: Action = Action::new(18, 2, 7, Status::None, 0 );
+	pub const CAREFUL_NOVICE: Action = Action::new(15, 2, 7, Status::None, 0 ); // below Careful Synthesis Mastery
 	pub const FOCUSED: Action = Action::new(20, 2, 12, Status::None, 0 );
-	pub const PRUDENT: Action = Action::new(18, 1, 18, Status::None, 0 );
-	pub const GROUNDWORK: Action = Action::new(36, 4, 18, Status::None, 0 );
+	pub const PRUDENT: Action = Action::new(18, 1, 18, Status::None, 0 ).blocked_by_waste_not();
+	pub const GROUNDWORK: Action = Action::new(36, 4, 18, Status::None, 0 ).halved_when_short();
+	pub const GROUNDWORK_NOVICE: Action = Action::new(30, 4, 18, Status::None, 0 ).halved_when_short(); // below Groundwork Mastery
 	pub const MUMEN: Action = Action::new(30, 2, 6, Status::MuscleMemory, 5 );
 	pub const VENER: Action = Action::new(0, 0, 18, Status::Veneration, 4 );
 	pub const MANIPULATION: Action = Action::new(0, 0, 96, Status::Manipulation, 8 );
 	pub const WN1: Action = Action::new(0, 0, 56, Status::WasteNot, 4 );
 	pub const WN2: Action = Action::new(0, 0, 98, Status::WasteNot, 8 );
 	pub const INTENSIVE: Action = Action::new(40, 2, 6, Status::None, 0 );
+	pub const DELICATE: Action = Action::new(15, 2, 32, Status::None, 0 ).touch(100);
+	pub const DELICATE_NOVICE: Action = Action::new(10, 2, 32, Status::None, 0 ).touch(100); // below Delicate Synthesis Mastery
+	pub const FINAL_APPRAISAL: Action = Action::new(0, 0, 1, Status::FinalAppraisal, 5 );
+	pub const TRAINED_PERFECTION: Action = Action::new(0, 0, 0, Status::TrainedPerfection, 0 );
+	pub const IMMACULATE_MEND: Action = Action::new(0, 0, 112, Status::ImmaculateMend, 0 );
+}
+
+/// CP and durability Reflect takes. The quality solver plays it and pays for it, but the opener that follows it
+/// has to make do without them.
+pub const REFLECT_CP: u16 = 18;
+pub const REFLECT_DURABILITY: u8 = 2;
+
+/// Why an action can't be used from a state. The state is left as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionError {
+    InsufficientCp,
+    InsufficientDurability,
+    NotFirstStep, // Muscle Memory or Reflect
+    OncePerCraft, // Trained Perfection or Heart and Soul
+    WasteNotActive, // Prudent Synthesis
+    NotLearned,
+    UnknownAction(char)
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionError::InsufficientCp => write!(f, "not enough CP"),
+            ActionError::InsufficientDurability => write!(f, "not enough durability"),
+            ActionError::NotFirstStep => write!(f, "only usable on the first step"),
+            ActionError::OncePerCraft => write!(f, "already used this craft"),
+            ActionError::WasteNotActive => write!(f, "unusable under Waste Not"),
+            ActionError::NotLearned => write!(f, "not learned at this level"),
+            ActionError::UnknownAction(c) => write!(f, "unknown action '{}'", c)
+        }
+    }
+}
+
+impl error::Error for ActionError {}
+
+/// An action of a user-supplied opener that can't be played, and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenerError {
+    pub position: usize,
+    pub action: char,
+    pub error: ActionError
 }
 
+impl fmt::Display for OpenerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "action {} ('{}'): {}", self.position + 1, self.action, self.error)
+    }
+}
+
+impl error::Error for OpenerError {}
+
 impl State {
-    pub fn apply_opener(&mut self, opener: &str, extra: char) {
+    /// Applies each action of `opener`, stopping at the first that can't be used.
+    pub fn apply_opener(&mut self, opener: &str) -> Result<(), ActionError> {
         for c in opener.chars() {
-            self.apply_char(c)
+            self.apply_char(c)?;
         }
-        self.apply_char(extra)
+        Ok(())
+    }
+
+    /// Plays a user-supplied `opener`, which also has to leave some durability after every action.
+    pub fn validate_opener(&mut self, opener: &str) -> Result<(), OpenerError> {
+        for (position, action) in opener.chars().enumerate() {
+            let error = match self.apply_char(action) {
+                Err(error) => error,
+                Ok(()) if self.durability == 0 => ActionError::InsufficientDurability,
+                Ok(()) => continue
+            };
+            return Err(OpenerError {position, action, error});
+        }
+        Ok(())
+    }
+
+    /// At least as much progress, CP, durability and buffs left, in no more time, with the same first step.
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.hands_over(other) && self.muscle_memory >= other.muscle_memory && self.final_appraisal >= other.final_appraisal
     }
 
-    pub fn apply_char(&mut self, c: char) {
+    /// Like `dominates`, but only over what the quality solver is handed once the opener ends.
+    pub fn hands_over(&self, other: &Self) -> bool {
+        self.progress >= other.progress && self.cp >= other.cp && self.durability >= other.durability
+            && self.manipulation >= other.manipulation && self.waste_not >= other.waste_not && self.veneration >= other.veneration
+            && self.inner_quiet >= other.inner_quiet && self.quality >= other.quality && self.time <= other.time
+            && (!self.heart_and_soul || other.heart_and_soul) && self.reflect == other.reflect
+            && (self.trained_perfection == other.trained_perfection || other.trained_perfection == Perfection::Used)
+    }
+
+    pub fn apply_char(&mut self, c: char) -> Result<(), ActionError> {
+        if c == ' ' {return Ok(());} // noop
+        let name = action_name(c).ok_or(ActionError::UnknownAction(c))?;
+        let version = qual::learned_version(name, self.level).ok_or(ActionError::NotLearned)?;
+        // the version a trait later replaces
+        let novice = version.superseded != u8::MAX;
+        if matches!(c, 'M' | 'R') && (self.time > 0 || self.reflect) {
+            return Err(ActionError::NotFirstStep);
+        }
         if c == 'R' {
-            self.durability -= 2;
-            self.cp -= 18;
-            self.inner_quiet += 2;
+            // Reflect is a quality action; this leaves the first step to the quality solver
+            if self.cp < REFLECT_CP {
+                return Err(ActionError::InsufficientCp);
+            }
+            self.cp -= REFLECT_CP;
+            self.durability = self.durability.saturating_sub(REFLECT_DURABILITY);
             self.reflect = true;
-            self.time += 3;
-            return;
+            return Ok(());
         }
-        if c == ' ' {return;} // noop
         self.apply_action(match c {
+            'b' if novice => &actions::BASIC_NOVICE,
             'b' => &actions::BASIC,
+            'c' if novice => &actions::CAREFUL_NOVICE,
             'c' => &actions::CAREFUL,
             'f' => &actions::FOCUSED,
             'p' => &actions::PRUDENT,
+            'g' if novice => &actions::GROUNDWORK_NOVICE,
             'g' => &actions::GROUNDWORK,
             'M' => &actions::MUMEN,
             'v' => &actions::VENER,
@@ -87,26 +217,30 @@ impl State {
             '1' => &actions::WN1,
             '2' => &actions::WN2,
             'i' => &actions::INTENSIVE,
-            _ => {panic!("Bad action char");}
-        });
+            'd' if novice => &actions::DELICATE_NOVICE,
+            'd' => &actions::DELICATE,
+            'a' => &actions::FINAL_APPRAISAL,
+            't' => &actions::TRAINED_PERFECTION,
+            'r' => &actions::IMMACULATE_MEND,
+            _ => {return Err(ActionError::UnknownAction(c));}
+        })
     }
 
-    pub fn apply_action(&mut self, act: &actions::Action) {
-        let w = self.waste_not;
-	    let v = self.veneration;
-	    let m = self.manipulation;
-	    let d = self.durability;
+    pub fn apply_action(&mut self, act: &actions::Action) -> Result<(), ActionError> {
+        let before = self.clone();
         if act.cp == 12 {
             self.tick_statuses(true);
         }
-        if (act.durability > self.durability * (if self.waste_not > 0 {2} else {1})) || act.cp > self.cp || (act.durability == 1 && self.waste_not == 0) {
-            self.waste_not = w;
-            self.veneration = v;
-            self.manipulation = m;
-            self.durability = d;
-            return;
+        if let Err(err) = self.usable(act) {
+            *self = before;
+            return Err(err);
+        }
+        let cost = self.durability_cost(act);
+        if act.durability > 0 && self.trained_perfection == Perfection::Active {
+            self.trained_perfection = Perfection::Used;
         }
-        self.durability -= act.durability >> (if self.waste_not > 0 {1} else {0});
+        let short = cost > self.durability;
+        self.durability = self.durability.saturating_sub(cost);
 	    self.cp -= act.cp;
 	    let mut action_progress = act.progress;
         if action_progress == 40 {self.heart_and_soul = true;}
@@ -115,133 +249,584 @@ impl State {
             action_progress += act.progress;
             self.muscle_memory = 0;
         }
+        if short && act.halved_when_short {action_progress /= 2;}
         if action_progress > 0 {
-            if act.progress == 20 {self.time += 2;}
+            if act.progress == 20 || act.progress == 40 {self.time += 2;} // Observe or Heart and Soul first
             self.time += 3;
         } else {
             self.time += 2;
         }
-        self.progress += action_progress;
-        let tick_manip = act.status != actions::Status::Manipulation;
-        self.tick_statuses(tick_manip);
+        if self.final_appraisal > 0 && self.progress + action_progress >= self.progress_limit {
+            self.progress = self.progress_limit - 1;
+            self.final_appraisal = 0;
+        } else {
+            self.progress += action_progress;
+        }
+        if act.quality > 0 {
+            self.quality += act.quality * (10 + self.inner_quiet as u16) / 10;
+            self.inner_quiet = min(self.inner_quiet + 1, 10);
+        }
+        // Final Appraisal isn't a step for the other buffs
+        if act.status != actions::Status::FinalAppraisal {
+            self.tick_statuses(act.status != actions::Status::Manipulation);
+        }
         match act.status {
             actions::Status::Manipulation => {self.manipulation = 8;}
             actions::Status::WasteNot => {self.waste_not = act.duration;}
             actions::Status::Veneration => {self.veneration = 4;}
             actions::Status::MuscleMemory => {self.muscle_memory = 5;}
+            actions::Status::FinalAppraisal => {self.final_appraisal = act.duration;}
+            actions::Status::TrainedPerfection => {self.trained_perfection = Perfection::Active;}
+            actions::Status::ImmaculateMend => {self.durability = self.max_durability;}
             _ => {}
         }
+        Ok(())
+    }
+
+    /// Durability `act` takes: none after Trained Perfection, and half under Waste Not, rounded up.
+    fn durability_cost(&self, act: &actions::Action) -> u8 {
+        if act.durability > 0 && self.trained_perfection == Perfection::Active {
+            0
+        } else if self.waste_not > 0 {
+            act.durability - act.durability / 2
+        } else {
+            act.durability
+        }
+    }
+
+    /// Any action can be used while the craft has durability left; running out before it completes breaks it.
+    fn usable(&self, act: &actions::Action) -> Result<(), ActionError> {
+        if act.cp > self.cp {
+            Err(ActionError::InsufficientCp)
+        } else if (act.status == actions::Status::TrainedPerfection && self.trained_perfection != Perfection::Available)
+            || (act.progress == 40 && self.heart_and_soul) {
+            Err(ActionError::OncePerCraft)
+        } else if act.blocked_by_waste_not && self.waste_not > 0 {
+            Err(ActionError::WasteNotActive)
+        } else if self.durability == 0 {
+            Err(ActionError::InsufficientDurability)
+        } else {
+            Ok(())
+        }
     }
 
     pub fn tick_statuses(&mut self, tick_manip: bool) {
         if self.waste_not > 0 {self.waste_not -= 1;}
         if self.veneration > 0 {self.veneration -= 1;}
         if self.muscle_memory > 0 {self.muscle_memory -= 1;}
+        if self.final_appraisal > 0 {self.final_appraisal -= 1;}
         if self.manipulation > 0 && tick_manip {
             self.manipulation -= 1;
-            self.durability += 1;
+            // a craft that has run out of durability is already broken
+            if self.durability > 0 {
+                self.durability = min(self.durability + 1, self.max_durability);
+            }
+        }
+    }
+}
+/// Actions an opener can continue with after its first step.
+const OPENER_ACTIONS: [char; 14] = ['m', 'v', '1', '2', 'a', 't', 'r', 'g', 'i', 'b', 'c', 'p', 'f', 'd'];
+
+/// Buff timers and once-per-craft flags of an opener state. States are only compared for dominance
+/// against states with the same key, which keeps the comparisons within small groups.
+type BuffKey = (u8, u8, u8, u8, u8, bool, bool, Perfection);
+
+/// The buffs and first step an opener hands over. Only states with at least these buffs and the same first
+/// step can hand over as much.
+type HandedKey = (bool, u8, u8, u8);
+
+fn buff_key(st: &State) -> BuffKey {
+    (st.manipulation, st.waste_not, st.veneration, st.muscle_memory, st.final_appraisal,
+        st.heart_and_soul, st.reflect, st.trained_perfection)
+}
+
+/// Keeps the states of `layer` that no state in `seen` or earlier in `layer` dominates, adding them to `seen`.
+fn prune_layer(layer: Vec<(String, State)>, seen: &mut HashMap<BuffKey, Vec<(usize, State)>>, next_id: &mut usize) -> Vec<(String, State)> {
+    let mut ids = Vec::with_capacity(layer.len());
+    for (_, st) in &layer {
+        let group = seen.entry(buff_key(st)).or_default();
+        if group.iter().any(|(_, other)| other.dominates(st)) {
+            ids.push(None);
+            continue;
         }
+        group.retain(|(_, other)| !st.dominates(other));
+        group.push((*next_id, st.clone()));
+        ids.push(Some(*next_id));
+        *next_id += 1;
+    }
+    // a state dominated by one later in its own layer isn't worth extending either
+    layer.into_iter().zip(ids).filter(|((_, st), id)| {
+        id.is_some_and(|id| seen[&buff_key(st)].iter().any(|(other, _)| *other == id))
+    }).map(|(entry, _)| entry).collect()
+}
+
+/// How far `generate_openers` searches, and how many openers of each length it returns.
+#[derive(Debug, Clone, Copy)]
+pub struct OpenerLimits {
+    pub max_len: usize,
+    // the cap can drop openers nothing dominates; None returns them all
+    pub max_per_length: Option<usize>
+}
+
+/// Searches openers of up to `limits.max_len` actions from `cp` and `durability`, keeping the non-dominated ones.
+/// Openers start with Muscle Memory, a step reserved for Reflect or a plain first action, keep durability
+/// above zero, and stay below `progress_limit` so the finisher still has something to do.
+/// Progress is only told apart at `thresholds`, the amounts some finisher needs to complete the craft.
+/// Each opener is paired with every finisher in its own quality search, so `limits.max_per_length` can cap how
+/// many of each length are returned, the ones leaving the most resources at each progress threshold first.
+/// Capping each length on its own means a longer search never drops a shorter opener.
+pub fn generate_openers(cp: u16, durability: u8, level: u8, has: bool, limits: OpenerLimits, progress_limit: u16, thresholds: &[u16]) -> Vec<String> {
+    let start = State {
+        time: 0,
+        inner_quiet: 0,
+        cp,
+        durability,
+        max_durability: durability,
+        manipulation: 0,
+        waste_not: 0,
+        veneration: 0,
+        muscle_memory: 0,
+        heart_and_soul: false,
+        reflect: false,
+        progress: 0,
+        level,
+        quality: 0,
+        final_appraisal: 0,
+        progress_limit,
+        trained_perfection: Perfection::Available
+    };
+    // the empty opener goes on with any action, as the first step of the craft
+    let mut layer: Vec<(String, State)> = vec![(String::new(), start.clone())];
+    for first in ['M', 'R'] {
+        let mut st = start.clone();
+        if learned_at(first) <= level && st.apply_char(first).is_ok() && st.durability > 0 && st.progress < progress_limit {
+            layer.push((first.to_string(), st));
+        }
+    }
+    let mut seen = HashMap::new();
+    let mut next_id = 0;
+    let mut kept: Vec<(String, State)> = Vec::new();
+    while !layer.is_empty() {
+        layer = prune_layer(layer, &mut seen, &mut next_id);
+        let mut next = Vec::new();
+        for (opener, st) in layer {
+            if opener.len() < limits.max_len {
+                for c in OPENER_ACTIONS {
+                    // buffs aren't refreshed while they're still running
+                    let running = match c {
+                        'm' => st.manipulation > 0,
+                        'v' => st.veneration > 0,
+                        '1' | '2' => st.waste_not > 0,
+                        'a' => st.final_appraisal > 0,
+                        'r' => st.durability == st.max_durability,
+                        'i' => !has || st.heart_and_soul,
+                        _ => false
+                    };
+                    if learned_at(c) > level || running {
+                        continue;
+                    }
+                    let mut new_st = st.clone();
+                    if new_st.apply_char(c).is_err() || new_st.durability == 0 || new_st.progress >= progress_limit {
+                        continue;
+                    }
+                    next.push((format!("{}{}", opener, c), new_st));
+                }
+            }
+            kept.push((opener, st));
+        }
+        layer = next;
+    }
+    // progress buffs still running are dropped at the phase boundary, but for Veneration a finisher can take over
+    let mut candidates: Vec<(String, State)> = Vec::new();
+    for (opener, mut st) in kept {
+        // a trailing buff or repair is better left to the quality solver, which can use it as well
+        if opener.ends_with(['m', 'v', '1', '2', 'a', 't', 'r']) {
+            continue;
+        }
+        let Some(&progress) = thresholds.iter().filter(|&&t| t <= st.progress).max() else {continue};
+        st.progress = progress;
+        if st.veneration < Carry::Veneration.steps() {
+            st.veneration = 0;
+        }
+        st.muscle_memory = 0;
+        st.final_appraisal = 0;
+        candidates.push((opener, st));
+    }
+    // sorted so that a state can only be handed over by one before it
+    candidates.sort_by_key(|(opener, st)| (Reverse((st.progress, st.cp, st.durability, st.manipulation, st.waste_not,
+        st.veneration, st.inner_quiet, st.quality)), st.time, st.heart_and_soul, st.trained_perfection == Perfection::Used, opener.len()));
+    let handed_key = |st: &State| (st.reflect, st.manipulation, st.waste_not, st.veneration);
+    let mut handed: HashMap<HandedKey, Vec<(String, State)>> = HashMap::new();
+    for (opener, st) in candidates {
+        let (reflect, manipulation, waste_not, veneration) = handed_key(&st);
+        let handed_over = handed.iter().any(|(&(r, m, w, v), group)|
+            r == reflect && m >= manipulation && w >= waste_not && v >= veneration
+                && group.iter().any(|(_, other)| other.hands_over(&st)));
+        if !handed_over {
+            handed.entry(handed_key(&st)).or_default().push((opener, st));
+        }
+    }
+    // take the openers leaving the most resources first, in turn from each progress threshold;
+    // durability and its buffs are priced at roughly the CP Master's Mend, Manipulation and Waste Not II cost per unit
+    let resources = |st: &State| st.cp as u32 + 15 * st.durability as u32 + 12 * st.manipulation as u32 + 6 * st.waste_not as u32;
+    let mut by_length: BTreeMap<usize, BTreeMap<u16, Vec<(String, State)>>> = BTreeMap::new();
+    for (opener, st) in handed.into_values().flatten() {
+        by_length.entry(opener.len()).or_default().entry(st.progress).or_default().push((opener, st));
     }
+    by_length.into_values().flat_map(|mut by_progress| {
+        for group in by_progress.values_mut() {
+            group.sort_by_key(|(opener, st)| (Reverse((resources(st), st.quality)), opener.clone()));
+        }
+        let rounds = by_progress.values().map(Vec::len).max().unwrap_or(0);
+        (0..rounds).flat_map(|rank| by_progress.values().filter_map(move |group| group.get(rank)))
+            .take(limits.max_per_length.unwrap_or(usize::MAX)).map(|(opener, _)| opener.clone()).collect::<Vec<_>>()
+    }).collect()
+}
+
+/// Name of the action an opener or finisher character plays, in the quality solver's action table.
+fn action_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        'b' => "Basic Synthesis",
+        'c' => "Careful Synthesis",
+        'f' => "Focused Synthesis",
+        'p' => "Prudent Synthesis",
+        'g' => "Groundwork",
+        'M' => "Muscle Memory",
+        'R' => "Reflect",
+        'v' => "Veneration",
+        'm' => "Manipulation",
+        '1' => "Waste Not I",
+        '2' => "Waste Not II",
+        'i' => "Intensive Synthesis", // Heart and Soul
+        'd' => "Delicate Synthesis",
+        'a' => "Final Appraisal",
+        't' => "Trained Perfection",
+        'r' => "Immaculate Mend",
+        _ => {return None;}
+    })
+}
+
+/// Level an opener or finisher action is learned at.
+pub fn learned_at(c: char) -> u8 {
+    if c == ' ' {1} else {action_name(c).map_or(u8::MAX, qual::learned_at)}
 }
-#[derive(Debug, PartialEq)]
-pub struct Finisher<'a> {
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finisher {
     pub time: u8,
     pub cp: u16,
     pub durability: u8,
     pub progress: u16,
+    // percent of base quality, from Delicate Synthesis; counted at no Inner Quiet, which the quality phase only adds to
+    pub quality: u16,
     pub heart_and_soul: bool,
-    pub description: &'a str
+    pub trained_perfection: bool,
+    pub carry: Carry, // buff the quality phase has to leave running
+    pub description: String
+}
+
+impl Finisher {
+    pub fn beats(&self, other: &Self) -> bool {
+        self.cp <= other.cp && self.durability <= other.durability && self.time <= other.time && self.quality >= other.quality
+            && (!self.heart_and_soul || other.heart_and_soul)
+            && (!self.trained_perfection || other.trained_perfection) && (self.carry == Carry::None || self.carry == other.carry)
+    }
+}
+
+/// Actions a finisher can be built from. Groundwork is left out as it loses efficiency without its full durability
+/// unless Waste Not is still running. Manipulation finishers are kept only where no cheaper finisher beats them,
+/// as each finisher is another quality search. Immaculate Mend is left to the quality phase.
+const FINISHER_ACTIONS: [char; 11] = ['m', 'v', '1', '2', 't', 'p', 'b', 'c', 'f', 'd', 'i'];
+const FINISHER_LENGTH: usize = 4;
+/// Durability to simulate finishers with, more than any finisher can need.
+const FINISHER_DURABILITY: u8 = 20;
+/// Most durability a finisher may ask the quality phase to leave, the range of its `min_durability`.
+const MAX_FINISHER_DURABILITY: u8 = 3;
+
+fn finisher_start(durability: u8, carry: Carry, level: u8) -> State {
+    State {
+        time: 0,
+        inner_quiet: 0,
+        cp: 1000,
+        durability,
+        max_durability: durability,
+        manipulation: 0,
+        waste_not: if carry == Carry::WasteNot {carry.steps()} else {0},
+        veneration: if carry == Carry::Veneration {carry.steps()} else {0},
+        muscle_memory: 0,
+        heart_and_soul: false,
+        reflect: false,
+        progress: 0,
+        level,
+        quality: 0,
+        final_appraisal: 0,
+        progress_limit: u16::MAX,
+        trained_perfection: Perfection::Available
+    }
 }
 
-impl Finisher<'_> {
-    pub const fn new(time: u8, cp: u16, durability: u8, progress: u16, heart_and_soul: bool, description: &str) -> Finisher {
-        Finisher {
-            time,
-            cp,
-            durability,
-            progress,
-            heart_and_soul,
-            description
+/// Least durability `description` can start with: every action but the last has to leave some behind,
+/// and a last Groundwork can't be left short of durability, as that would halve it.
+fn finisher_durability(description: &str, carry: Carry, level: u8) -> u8 {
+    let play = |durability| {
+        let mut st = finisher_start(durability, carry, level);
+        let mut actions = description.chars().peekable();
+        while let Some(c) = actions.next() {
+            if st.apply_char(c).is_err() || (st.durability == 0 && actions.peek().is_some()) {
+                return None;
+            }
         }
+        Some(st.progress)
+    };
+    let progress = play(FINISHER_DURABILITY);
+    (1..FINISHER_DURABILITY).find(|&durability| play(durability) == progress).unwrap_or(FINISHER_DURABILITY)
+}
+
+/// Finishers usable at crafter level `level`, built from the progress actions and pruned by dominance.
+/// Buffs aren't refreshed while running and every finisher ends on a synthesis.
+/// Finishers may also start under each buff in `carried`, left running by the opener or the quality phase:
+/// Waste Not lets them use Groundwork, and Veneration boosts their first steps.
+pub fn finishers(level: u8, carried: &[Carry]) -> Vec<Finisher> {
+    let mut kept: Vec<Finisher> = Vec::new();
+    add_finishers(&mut kept, Carry::None, FINISHER_LENGTH, level);
+    for &carry in carried {
+        let length = if carry == Carry::WasteNot {carry.steps() as usize} else {FINISHER_LENGTH};
+        add_finishers(&mut kept, carry, length, level);
     }
+    kept
+}
 
-    pub fn beats(&self, other: &Self) -> bool {
-        self.cp <= other.cp && self.durability <= other.durability && self.time <= other.time && !(self.heart_and_soul && !other.heart_and_soul)
-    }
-}
-
-pub const FINISHERS: [&Finisher; 24] = [
-    &Finisher::new(3, 0, 1, 12, false, "b"),
-    &Finisher::new(3, 7, 1, 18, false, "c"),
-    &Finisher::new(5, 12, 1, 20, false, "f"),
-    &Finisher::new(5, 6, 1, 40, true, "i"),
-    &Finisher::new(5, 25, 1, 27, false, "vc"),
-    &Finisher::new(7, 30, 1, 30, false, "vf"),
-    &Finisher::new(7, 24, 1, 60, true, "vi"),
-    &Finisher::new(6, 18, 2, 30, false, "pb"),
-    &Finisher::new(6, 25, 2, 36, false, "pc"),
-    &Finisher::new(8, 30, 2, 38, false, "pf"),
-    &Finisher::new(8, 24, 2, 58, true, "pi"),
-    &Finisher::new(8, 43, 2, 54, false, "vpc"),
-    &Finisher::new(10, 48, 2, 57, false, "vpf"),
-    &Finisher::new(8, 36, 2, 45, false, "vpb"),
-    &Finisher::new(10, 42, 2, 87, true, "vpi"),
-    &Finisher::new(8, 12, 3, 32, false, "bf"),
-    &Finisher::new(6, 7, 3, 30, false, "bc"),
-    &Finisher::new(8, 19, 3, 38, false, "cf"),
-    &Finisher::new(6, 0, 3, 24, false, "bb"),
-    &Finisher::new(6, 14, 3, 36, false, "cc"),
-    &Finisher::new(10, 24, 3, 40, false, "ff"),
-    &Finisher::new(8, 6, 3, 52, true, "bi"),
-    &Finisher::new(8, 13, 3, 58, true, "ci"),
-    &Finisher::new(10, 17, 3, 60, true, "fi"),
-];
-
-pub const OPENERS: [&str; 46] = [ 
-    "Mmv1g",
-    "Mmv2g",
-    "Mmv1gg",
-    "Mmv2gg",
-    "Mmv1ggg",
-    "Mmv2ggg",
-    "Mmv1ig",
-    "Mmv2ig",
-    "Mmv1igg",
-    "Mmv2igg",
-    "Mmv1iggg",
-    "Mmv2iggg",
-    "Mmvi1g",
-    "Mmvi2g",
-    "Mmvi1gg",
-    "Mmvi2gg",
-    "Mmvi1ggg",
-    "Mmvi2ggg",
-    "Rmv1gg",
-    "Rmv2gg",
-    "Rmv1ggg",
-    "Rmv2ggg",
-    "Rmv1gggg",
-    "Rmv2gggg",
-    "Rmv2ggggg",
-    "Rmv1ig",
-    "Rmv2ig",
-    "Rmv1igg",
-    "Rmv2igg",
-    "Rmv1iggg",
-    "Rmv2iggg",
-    "Rmv2igggg",
-    "Rmvi1g",
-    "Rmvi2g",
-    "Rmvi1gg",
-    "Rmvi2gg",
-    "Rmvi1ggg",
-    "Rmvi2ggg",
-    "Rmvi2gggg",
-    "R",
-    "M",
-    "Mmvipp",
-    "M1g",
-    "M2g",
-    "Mi",
-    "Mg"
-];
\ No newline at end of file
+/// Adds the finishers of up to `length` actions that start under `carry` to `kept`.
+fn add_finishers(kept: &mut Vec<Finisher>, carry: Carry, length: usize, level: u8) {
+    let mut layer = vec![(String::new(), finisher_start(FINISHER_DURABILITY, carry, level))];
+    for _ in 0..length {
+        let mut next = Vec::new();
+        for (description, st) in &layer {
+            let groundwork = if carry == Carry::WasteNot {Some('g')} else {None};
+            for c in FINISHER_ACTIONS.into_iter().chain(groundwork) {
+                let running = match c {
+                    'm' => st.manipulation > 0,
+                    'v' => st.veneration > 0,
+                    '1' | '2' => st.waste_not > 0,
+                    'i' => st.heart_and_soul,
+                    _ => false
+                };
+                if learned_at(c) > level || running {
+                    continue;
+                }
+                let mut new_st = st.clone();
+                if new_st.apply_char(c).is_err() || new_st.durability == 0 {
+                    continue;
+                }
+                let description = format!("{}{}", description, c);
+                let durability = finisher_durability(&description, carry, level);
+                if durability > MAX_FINISHER_DURABILITY {
+                    continue;
+                }
+                if new_st.progress > st.progress {
+                    let finisher = Finisher {
+                        time: new_st.time,
+                        cp: 1000 - new_st.cp,
+                        durability,
+                        progress: new_st.progress,
+                        quality: new_st.quality,
+                        heart_and_soul: new_st.heart_and_soul,
+                        trained_perfection: new_st.trained_perfection != Perfection::Available,
+                        // a single requirement, as each one is another quality search over all its states
+                        carry,
+                        description: description.clone()
+                    };
+                    if !kept.iter().any(|f| f.progress >= finisher.progress && f.beats(&finisher)) {
+                        kept.retain(|f| !(finisher.progress >= f.progress && finisher.beats(f)));
+                        kept.push(finisher);
+                    }
+                }
+                next.push((description, new_st));
+            }
+        }
+        layer = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::actions::{Action, Status};
+
+    fn limits(max_len: usize, max_per_length: Option<usize>) -> OpenerLimits {
+        OpenerLimits {max_len, max_per_length}
+    }
+
+    fn state(durability: u8) -> State {
+        finisher_start(durability, Carry::None, 100)
+    }
+
+    #[test]
+    fn groundwork_halved_when_short() {
+        let mut st = state(3);
+        st.apply_char('g').unwrap();
+        assert_eq!((st.progress, st.durability), (18, 0));
+        let mut st = state(4);
+        st.apply_char('g').unwrap();
+        assert_eq!((st.progress, st.durability), (36, 0));
+    }
+
+    #[test]
+    fn groundwork_full_under_waste_not() {
+        let mut st = state(2);
+        st.waste_not = 1;
+        st.apply_char('g').unwrap();
+        assert_eq!((st.progress, st.durability), (36, 0));
+    }
+
+    #[test]
+    fn waste_not_rounds_cost_up() {
+        let mut st = state(4);
+        st.waste_not = 2;
+        st.apply_char('b').unwrap();
+        assert_eq!(st.durability, 3);
+        st.apply_action(&Action::new(10, 1, 0, Status::None, 0)).unwrap();
+        assert_eq!(st.durability, 2);
+    }
+
+    #[test]
+    fn prudent_blocked_by_waste_not() {
+        let mut st = state(4);
+        st.waste_not = 1;
+        assert_eq!(st.apply_char('p'), Err(ActionError::WasteNotActive));
+        assert_eq!(st.durability, 4);
+    }
+
+    #[test]
+    fn usable_until_durability_runs_out() {
+        let mut st = state(1);
+        st.apply_char('b').unwrap();
+        assert_eq!(st.durability, 0);
+        assert_eq!(st.apply_char('b'), Err(ActionError::InsufficientDurability));
+    }
+
+    #[test]
+    fn manipulation_capped_at_max_durability() {
+        let mut st = state(4);
+        st.manipulation = 3;
+        st.apply_char('v').unwrap();
+        assert_eq!((st.durability, st.manipulation), (4, 2));
+    }
+
+    #[test]
+    fn final_appraisal_does_not_tick() {
+        let mut st = state(2);
+        st.manipulation = 3;
+        st.waste_not = 2;
+        st.apply_char('a').unwrap();
+        assert_eq!((st.durability, st.manipulation, st.waste_not), (2, 3, 2));
+    }
+
+    #[test]
+    fn manipulation_does_not_revive_broken_craft() {
+        let mut st = state(2);
+        st.manipulation = 3;
+        st.apply_char('b').unwrap();
+        assert_eq!(st.durability, 0);
+    }
+
+    #[test]
+    fn trained_perfection_once_per_craft() {
+        let mut st = state(4);
+        st.apply_char('t').unwrap();
+        st.apply_char('g').unwrap();
+        assert_eq!((st.durability, st.trained_perfection), (4, Perfection::Used));
+        assert_eq!(st.apply_char('t'), Err(ActionError::OncePerCraft));
+    }
+
+    #[test]
+    fn reflect_costs_durability() {
+        let mut st = state(6);
+        st.apply_char('R').unwrap();
+        assert_eq!((st.cp, st.durability), (1000 - REFLECT_CP, 4));
+        let mut st = state(6);
+        let err = st.validate_opener("R1gg").unwrap_err();
+        assert_eq!((err.position, err.error), (3, ActionError::InsufficientDurability));
+    }
+
+    #[test]
+    fn openers_below_muscle_memory_level() {
+        let openers = generate_openers(200, 8, 50, false, limits(4, None), 100, &[0, 30]);
+        assert!(!openers.is_empty());
+        assert!(openers.iter().all(|opener| !opener.starts_with(['M', 'R'])));
+    }
+
+    #[test]
+    fn unlearned_actions_rejected() {
+        let mut st = finisher_start(8, Carry::None, 20);
+        assert_eq!(st.apply_char('g'), Err(ActionError::NotLearned));
+        assert_eq!(st.apply_char('M'), Err(ActionError::NotLearned));
+        let err = st.validate_opener("vb2").unwrap_err();
+        assert_eq!((err.position, err.error), (2, ActionError::NotLearned));
+    }
+
+    #[test]
+    fn low_level_openers_learned() {
+        let openers = generate_openers(300, 12, 20, true, limits(4, None), 100, &[0, 30]);
+        assert!(!openers.is_empty());
+        assert!(openers.iter().all(|opener| opener.chars().all(|c| learned_at(c) <= 20)), "{:?}", openers);
+        assert!(finishers(20, &[Carry::WasteNot, Carry::Veneration]).iter()
+            .all(|f| f.description.chars().all(|c| learned_at(c) <= 20)));
+    }
+
+    #[test]
+    fn openers_end_in_intensive_synthesis() {
+        let openers = generate_openers(400, 12, 90, true, limits(2, None), 500, &(0..500).collect::<Vec<u16>>());
+        assert!(openers.iter().any(|opener| opener == "Mi"));
+    }
+
+    #[test]
+    fn openers_capped() {
+        let thresholds: Vec<u16> = (0..500).collect();
+        let capped = generate_openers(400, 12, 100, true, limits(5, Some(40)), 500, &thresholds);
+        for len in 0..=5 {
+            assert!(capped.iter().filter(|opener| opener.len() == len).count() <= 40);
+        }
+        // uncapped, every opener nothing dominates is kept
+        let all = generate_openers(400, 12, 100, true, limits(5, None), 500, &thresholds);
+        assert!(all.len() > capped.len());
+        assert!(capped.iter().all(|opener| all.contains(opener)));
+    }
+
+    #[test]
+    fn longer_search_keeps_shorter_openers() {
+        let thresholds: Vec<u16> = (0..500).collect();
+        // the cap is per length, so longer openers can't take a shorter one's place
+        let short = generate_openers(400, 12, 100, true, limits(4, Some(40)), 500, &thresholds);
+        let long = generate_openers(400, 12, 100, true, limits(6, Some(40)), 500, &thresholds);
+        assert!(short.iter().all(|opener| long.contains(opener)));
+    }
+
+    #[test]
+    fn delicate_finishers_kept() {
+        let finishers = finishers(100, &[]);
+        let delicate: Vec<&Finisher> = finishers.iter().filter(|f| f.description.contains('d')).collect();
+        assert!(!delicate.is_empty());
+        // Careful Synthesis makes more progress for less CP, but no quality
+        assert!(delicate.iter().all(|f| f.quality >= 100));
+    }
+
+    #[test]
+    fn carried_buffs_in_finishers() {
+        let finishers = finishers(100, &[Carry::WasteNot, Carry::Veneration]);
+        // only Waste Not left running lets a finisher use Groundwork at full efficiency
+        assert!(finishers.iter().any(|f| f.carry == Carry::WasteNot && f.description.contains('g')));
+        assert!(finishers.iter().filter(|f| f.carry != Carry::WasteNot).all(|f| !f.description.contains('g')));
+        // Veneration left running is never recast
+        assert!(finishers.iter().any(|f| f.carry == Carry::Veneration));
+        assert!(finishers.iter().filter(|f| f.carry == Carry::Veneration).all(|f| !f.description.contains('v')));
+    }
+
+    #[test]
+    fn finishers_pruned_by_dominance() {
+        let finishers = finishers(100, &[Carry::WasteNot, Carry::Veneration]);
+        for (i, f) in finishers.iter().enumerate() {
+            assert!(!finishers.iter().enumerate().any(|(j, g)| i != j && g.progress >= f.progress && g.beats(f)));
+        }
+    }
+}
@@ -2,7 +2,6 @@ use std::collections::HashMap;
 use std::cmp::{max, min};
 use std::fmt;
 use std::num::{NonZero, NonZeroU64};
-use std::os::raw;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, Copy)]
@@ -11,858 +10,1597 @@ pub struct State {
     pub inner_quiet: u8, // 0-10, 4 bits
     pub cp: u16, // 0-1023, 10 bits
     pub durability: u8, // 0-16, 5 bits
-    pub manipulation: u8, // 0-8, 4 bits
-    pub waste_not: u8, // 0-8, 4 bits
-    pub innovation: u8, // 0-4, 3 bits
-    pub great_strides: u8, // 0-3, 2 bits
+    pub manipulation: u8, // 0-10, 4 bits
+    pub waste_not: u8, // 0-10, 4 bits
+    pub innovation: u8, // 0-6, 3 bits
+    pub great_strides: u8, // 0-5, 3 bits
     pub min_durability: u8, // 0-3, 2 bits
-    pub trained_perfection: u8, // 0-2, 2 bits // TODO: Refactor this to combo instead?
+    pub trained_perfection: Perfection, // 0-2, 2 bits
     pub heart_and_soul: bool, // 1 bit
+    pub condition: Condition, // 0-8, 4 bits
+    pub combo: Combo, // 0-3, 2 bits
+    pub first_step: bool, // 1 bit
+    pub quick_innovation: bool, // 1 bit
+    pub expedience: bool, // 1 bit
+    pub veneration: u8, // 0-6, 3 bits
+    pub muscle_memory: u8, // 0-7, 3 bits
+    pub progress: u16, // remaining points, low 6 bits; the rest goes in the key beside the packed state
+    pub final_appraisal: u8, // steps left, 0-7, 3 bits
+    pub carry: Carry, // 0-2, 2 bits
+    pub tricks: u8, // Tricks of the Trade uses left without a step budget, stored in place of time
 }
 
 impl State {
     pub fn unpack(st: u64) -> State {
-        State { 
-            time:                ((st >> 37) & 0xFF) as u8, // yes
-            inner_quiet:         ((st >> 33) & 0xF) as u8, // 4
-            cp:                  ((st >> 23) & 0x3FF) as u16, // 10
-            durability:          ((st >> 18) & 0x1F) as u8, // 5
-            manipulation:        ((st >> 14) & 0xF) as u8, // 4
-            waste_not:           ((st >> 10) & 0xF) as u8, // 4
-            innovation:          ((st >> 7) & 0x7) as u8, // 3
-            great_strides:       ((st >> 5) & 0x3) as u8, // 2
+        State {
+            time:                0, // stores key time separately
+            tricks:              0, // likewise
+            carry:               Carry::from_index(((st >> 62) & 0x3) as u8), // 2
+            final_appraisal:     ((st >> 59) & 0x7) as u8, // 3
+            progress:            ((st >> 53) & 0x3F) as u16, // 6, the low bits
+            muscle_memory:       ((st >> 50) & 0x7) as u8, // 3
+            veneration:          ((st >> 47) & 0x7) as u8, // 3
+            expedience:          ((st >> 46) & 0x1) != 0, // 1
+            quick_innovation:    ((st >> 45) & 0x1) != 0, // 1
+            first_step:          ((st >> 44) & 0x1) != 0, // 1
+            combo:               Combo::from_index(((st >> 42) & 0x3) as u8), // 2
+            condition:           Condition::from_index(((st >> 38) & 0xF) as u8), // 4
+            inner_quiet:         ((st >> 34) & 0xF) as u8, // 4
+            cp:                  ((st >> 24) & 0x3FF) as u16, // 10
+            durability:          ((st >> 19) & 0x1F) as u8, // 5
+            manipulation:        ((st >> 15) & 0xF) as u8, // 4
+            waste_not:           ((st >> 11) & 0xF) as u8, // 4
+            innovation:          ((st >> 8) & 0x7) as u8, // 3
+            great_strides:       ((st >> 5) & 0x7) as u8, // 3
             min_durability:      ((st >> 3) & 0x3) as u8, // 2
-            trained_perfection:  ((st >> 1) & 0x3) as u8, // 2
-            heart_and_soul:      ((st & 0x1) != 00)       // 1 
+            trained_perfection:  Perfection::from_index(((st >> 1) & 0x3) as u8), // 2
+            heart_and_soul:      ((st & 0x1) != 00)       // 1
         }
     }
 
-    pub fn index(&self, check_time: bool) -> u64 {
+    pub fn index(&self) -> u64 {
         (self.heart_and_soul as u64) // 1
         + ((self.trained_perfection as u64) << 1)
         + ((self.min_durability as u64) << 3)
-        + ((self.great_strides as u64) << 5) // 2
-        + ((self.innovation as u64) << 7) // 3
-        + ((self.waste_not as u64) << 10) // 4
-        + ((self.manipulation as u64) << 14) // 4
-        + ((self.durability as u64) << 18) // 5
-        + ((self.cp as u64) << 23) // 10
-        + ((self.inner_quiet as u64) << 33) // 4
-        + if check_time {(self.time as u64) << 37} else {0}  // 7
-        // the overall space requirement is
-        // 90 * 11 * 1000 * 17 * 9 * 9 * 5 * 4 * 2 = 38B
-        // too large for an array so a hashmap is best
-    }
-    
+        + ((self.great_strides as u64) << 5) // 3
+        + ((self.innovation as u64) << 8) // 3
+        + ((self.waste_not as u64) << 11) // 4
+        + ((self.manipulation as u64) << 15) // 4
+        + ((self.durability as u64) << 19) // 5
+        + ((self.cp as u64) << 24) // 10
+        + ((self.inner_quiet as u64) << 34) // 4
+        + ((self.condition as u64) << 38) // 4
+        + ((self.combo as u64) << 42) // 2
+        + ((self.first_step as u64) << 44) // 1
+        + ((self.quick_innovation as u64) << 45) // 1
+        + ((self.expedience as u64) << 46) // 1
+        + ((self.veneration as u64) << 47) // 3
+        + ((self.muscle_memory as u64) << 50) // 3
+        + (((self.progress & 0x3F) as u64) << 53) // 6
+        + ((self.final_appraisal as u64) << 59) // 3
+        + ((self.carry as u64) << 62) // 2
+        // all 64 bits are used; the time or Tricks of the Trade uses and the progress above 6 bits are kept
+        // beside the index. Every field at full range is far too many states for an array, so the default store
+        // is hashed, and the DenseStore only covers the ranges the cache's actions can reach
+    }
+
+    /// At least as many resources and buffs as `other` with nothing else differing, so at least as much quality.
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.time >= other.time && self.inner_quiet >= other.inner_quiet && self.cp >= other.cp
+            && self.durability >= other.durability && self.manipulation >= other.manipulation
+            && self.waste_not >= other.waste_not && self.innovation >= other.innovation
+            && self.great_strides >= other.great_strides && self.min_durability <= other.min_durability
+            && self.trained_perfection == other.trained_perfection && self.heart_and_soul >= other.heart_and_soul
+            && self.condition == other.condition && self.combo == other.combo && self.first_step == other.first_step
+            && self.quick_innovation >= other.quick_innovation && self.expedience == other.expedience
+            && self.veneration == other.veneration && self.muscle_memory == other.muscle_memory
+            && self.progress == other.progress && self.final_appraisal == other.final_appraisal
+            && (self.carry == Carry::None || self.carry == other.carry) && self.tricks >= other.tricks
+    }
+
+    /// Remaining progress above the bits `index` packs, kept beside it.
+    pub fn progress_beside(progress: u16) -> usize {
+        progress as usize >> 6
+    }
+
+    /// Whether the craft can be handed to the finisher here, with the durability and carried buff it needs.
+    pub fn can_finish(&self) -> bool {
+        let carried = match self.carry {
+            Carry::None => true,
+            Carry::WasteNot => self.waste_not >= Carry::WasteNot.steps(),
+            Carry::Veneration => self.veneration >= Carry::Veneration.steps()
+        };
+        self.durability >= self.min_durability && carried
+    }
+
  }
 
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "T: {}, I: {}, CP: {}, D: {}>{}, MANIP{}, WN{}, IN{}, GS{}, TP{}, HaS? {}", 
-            self.time, self.inner_quiet, self.cp, self.durability, self.min_durability, self.manipulation, 
-            self.waste_not, self.innovation, self.great_strides, self.trained_perfection, self.heart_and_soul)
+        write!(f, "T: {}, I: {}, CP: {}, D: {}>{}, MANIP{}, WN{}, IN{}, GS{}, TP {:?}, HaS? {}, {:?}, combo {:?}, first? {}, QI? {}, Exp? {}, P{}, VEN{}, MM{}, FA? {}, carry {:?}, ToT{}",
+            self.time, self.inner_quiet, self.cp, self.durability, self.min_durability, self.manipulation,
+            self.waste_not, self.innovation, self.great_strides, self.trained_perfection, self.heart_and_soul,
+            self.condition, self.combo, self.first_step,
+            self.quick_innovation, self.expedience, self.progress, self.veneration, self.muscle_memory, self.final_appraisal, self.carry, self.tricks)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Normal,
+    Good,
+    Excellent,
+    Poor,
+    Centered,
+    Sturdy,
+    Pliant,
+    Malleable,
+    Primed
+}
+
+impl Condition {
+    pub fn from_index(index: u8) -> Condition {
+        match index {
+            1 => Condition::Good,
+            2 => Condition::Excellent,
+            3 => Condition::Poor,
+            4 => Condition::Centered,
+            5 => Condition::Sturdy,
+            6 => Condition::Pliant,
+            7 => Condition::Malleable,
+            8 => Condition::Primed,
+            _ => Condition::Normal
+        }
+    }
+}
+
+/// The action last used, where it can open a combo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combo {
+    None,
+    BasicTouch,
+    StandardTouch,
+    Observe
+}
+
+impl Combo {
+    pub fn from_index(index: u8) -> Combo {
+        match index {
+            1 => Combo::BasicTouch,
+            2 => Combo::StandardTouch,
+            3 => Combo::Observe,
+            _ => Combo::None
+        }
+    }
+}
+
+/// Trained Perfection can be used once per craft, and lasts until the next step that costs durability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Perfection {
+    Available,
+    Active,
+    Used
+}
+
+impl Perfection {
+    pub fn from_index(index: u8) -> Perfection {
+        match index {
+            1 => Perfection::Active,
+            2 => Perfection::Used,
+            _ => Perfection::Available
+        }
+    }
+}
+
+/// Tricks of the Trade uses a craft gets by default without a step budget. Each needs a Good or Excellent
+/// condition, so without a step budget to bound the rolls a Normal step could always be followed by another one.
+/// Each use more multiplies the states CP can reach; `DPCache::tricks` sets the cap.
+pub const TRICKS_WITHOUT_TIME: u8 = 2;
+
+/// Values a slot can take: steps left in the budget, or Tricks of the Trade uses left. The hashed store keeps a
+/// map for every u8, so no slot can land in another's map.
+const SLOTS: usize = u8::MAX as usize + 1;
+
+/// Step budgets the dense store has positions for; states with more time left go to its overflow.
+const DENSE_TIMES: u64 = 120;
+
+/// Buff a finisher takes over from the quality phase, which has to leave it running for `steps` more steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Carry {
+    None,
+    WasteNot,
+    Veneration
+}
+
+impl Carry {
+    pub fn from_index(index: u8) -> Carry {
+        match index {
+            1 => Carry::WasteNot,
+            2 => Carry::Veneration,
+            _ => Carry::None
+        }
+    }
+
+    pub const fn steps(self) -> u8 {
+        match self {
+            Carry::None => 0,
+            Carry::WasteNot => 3,
+            Carry::Veneration => 2
+        }
+    }
+}
+
+/// Which conditions a craft can roll, and how likely each is after a given condition.
+/// The most likely follow-up is listed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConditionTable {
+    NormalOnly,
+    Standard,
+    Expert
+}
+
+const NORMAL_ONLY: [(Condition, f64); 1] = [(Condition::Normal, 1.)];
+const STANDARD_FROM_NORMAL: [(Condition, f64); 3] = [(Condition::Normal, 0.76), (Condition::Good, 0.2), (Condition::Excellent, 0.04)];
+const STANDARD_FROM_EXCELLENT: [(Condition, f64); 1] = [(Condition::Poor, 1.)];
+const EXPERT: [(Condition, f64); 7] = [(Condition::Normal, 0.22), (Condition::Centered, 0.15), (Condition::Sturdy, 0.15),
+    (Condition::Good, 0.12), (Condition::Pliant, 0.12), (Condition::Malleable, 0.12), (Condition::Primed, 0.12)];
+
+impl ConditionTable {
+    pub fn for_recipe(expert: bool) -> ConditionTable {
+        if expert {ConditionTable::Expert} else {ConditionTable::Standard}
+    }
+
+    pub fn transitions(&self, from: Condition) -> &'static [(Condition, f64)] {
+        match self {
+            ConditionTable::NormalOnly => &NORMAL_ONLY,
+            ConditionTable::Standard => match from {
+                Condition::Normal => &STANDARD_FROM_NORMAL,
+                Condition::Excellent => &STANDARD_FROM_EXCELLENT,
+                _ => &NORMAL_ONLY
+            },
+            ConditionTable::Expert => &EXPERT
+        }
+    }
+}
+
+/// Slots in each page of a dense store, 512 KiB.
+const PAGE_SLOTS: u64 = 1 << 16;
+
+/// Array over a mixed-radix encoding of every `State` field, for full precomputation.
+/// Each radix covers the values the cache's actions can reach; fields that can't vary get radix 1 and
+/// aren't stored. The array is split into pages allocated on first use, up to a budget. States outside
+/// the ranges, such as ones handed over with more buffs than the actions give, and states whose page
+/// is past the budget, go to a hashed overflow instead.
+/// Slots hold the result plus one, so a fresh page reads as empty.
+#[derive(Serialize, Deserialize)]
+pub struct DenseStore {
+    radices: [u64; 21],
+    pages: Vec<Option<Box<[u64]>>>,
+    allocated: u64,
+    max_pages: u64,
+    overflow: HashMap<(u8, u16, u64), u64>
+}
+
+impl DenseStore {
+    /// Store for every state of `cache`, allocating at most `max_slots` of them; an error if the states
+    /// can't even be numbered.
+    pub fn new(cache: &DPCache, carried: &[Carry], max_slots: u64) -> Result<DenseStore, String> {
+        let slots = Self::slots(cache, carried);
+        let pages = slots.div_ceil(PAGE_SLOTS);
+        if slots == u64::MAX || usize::try_from(pages).is_err() {
+            return Err("dense store has more states than can be numbered".to_string());
+        }
+        Ok(DenseStore {
+            radices: Self::radices(cache, carried),
+            pages: vec![None; pages as usize],
+            allocated: 0,
+            max_pages: max_slots / PAGE_SLOTS,
+            overflow: HashMap::new()
+        })
+    }
+
+    /// Number of slots a store for `cache` needs.
+    pub fn slots(cache: &DPCache, carried: &[Carry]) -> u64 {
+        Self::radices(cache, carried).iter().fold(1, |slots: u64, radix| slots.saturating_mul(*radix))
+    }
+
+    /// `carried` lists the buffs finishers may take over from the quality phase.
+    /// Fields the cache's actions can't read are cleared before a state is stored, so they get radix 1.
+    fn radices(cache: &DPCache, carried: &[Carry]) -> [u64; 21] {
+        let primed = if cache.conditions == ConditionTable::Expert {2} else {0};
+        let unified = cache.max_progress > 0;
+        let read = &cache.read;
+        let varies = |radix: u64, read: bool| if read {radix} else {1};
+        [
+            varies(2, read.heart_and_soul),
+            varies(3, read.trained_perfection),
+            // the unified solver finishes the craft itself
+            varies(4, !unified), // min_durability
+            varies(4 + primed, read.great_strides),
+            varies(5 + primed, read.innovation),
+            9 + primed, // waste_not
+            9 + primed, // manipulation
+            cache.max_dur as u64 + 1,
+            cache.max_cp as u64 + 1,
+            11, // inner_quiet
+            match cache.conditions {
+                ConditionTable::NormalOnly => 1,
+                // Normal, Good, Excellent and Poor
+                ConditionTable::Standard => 4,
+                ConditionTable::Expert => 9
+            },
+            varies(4, read.combo),
+            varies(2, read.first_step),
+            varies(2, read.quick_innovation),
+            varies(2, read.expedience),
+            varies(5 + primed, unified || carried.contains(&Carry::Veneration)),
+            varies(6 + primed, unified), // muscle_memory
+            cache.max_progress as u64 + 1,
+            varies(6 + primed, unified), // final_appraisal
+            varies(3, !unified && !carried.is_empty()), // carry
+            if cache.check_time {DENSE_TIMES} else {varies(cache.tricks as u64 + 1, read.tricks)}
+        ]
+    }
+
+    /// Slots allocated so far.
+    pub fn allocated(&self) -> u64 {
+        self.allocated * PAGE_SLOTS
+    }
+
+    /// `slot` is the state's time, or its Tricks of the Trade uses left without a step budget.
+    fn position(&self, state: &State, slot: u8) -> Option<u64> {
+        let digits = [
+            state.heart_and_soul as u64,
+            state.trained_perfection as u64,
+            state.min_durability as u64,
+            state.great_strides as u64,
+            state.innovation as u64,
+            state.waste_not as u64,
+            state.manipulation as u64,
+            state.durability as u64,
+            state.cp as u64,
+            state.inner_quiet as u64,
+            state.condition as u64,
+            state.combo as u64,
+            state.first_step as u64,
+            state.quick_innovation as u64,
+            state.expedience as u64,
+            state.veneration as u64,
+            state.muscle_memory as u64,
+            state.progress as u64,
+            state.final_appraisal as u64,
+            state.carry as u64,
+            slot as u64
+        ];
+        let mut position = 0;
+        for (digit, radix) in digits.iter().zip(self.radices).rev() {
+            if *digit >= radix {
+                return None;
+            }
+            position = position * radix + digit;
+        }
+        Some(position)
+    }
+
+    /// Overflow key: the slot and the progress the packed state has no room for, beside the packed state.
+    fn overflow_key(state: &State, slot: u8) -> (u8, u16, u64) {
+        (slot, State::progress_beside(state.progress) as u16, state.index())
+    }
+
+    pub fn get(&self, state: &State, slot: u8) -> Option<u64> {
+        let page = self.position(state, slot).and_then(|pos| Some((self.pages[(pos / PAGE_SLOTS) as usize].as_ref()?, pos)));
+        match page {
+            Some((page, pos)) => page[(pos % PAGE_SLOTS) as usize].checked_sub(1),
+            None => self.overflow.get(&Self::overflow_key(state, slot)).copied()
+        }
+    }
+
+    pub fn insert(&mut self, state: &State, slot: u8, value: u64) -> Option<u64> {
+        let Some(pos) = self.position(state, slot) else {
+            return self.overflow.insert(Self::overflow_key(state, slot), value)
+        };
+        let page = &mut self.pages[(pos / PAGE_SLOTS) as usize];
+        if page.is_none() {
+            if self.allocated == self.max_pages {
+                return self.overflow.insert(Self::overflow_key(state, slot), value)
+            }
+            *page = Some(vec![0; PAGE_SLOTS as usize].into_boxed_slice());
+            self.allocated += 1;
+        }
+        let slot_value = &mut page.as_mut().unwrap()[(pos % PAGE_SLOTS) as usize];
+        let prev = slot_value.checked_sub(1);
+        *slot_value = value + 1;
+        prev
+    }
+}
+
+/// Which of the `State` fields that only some actions use the cache's actions read or set.
+/// The others can't change a state's value, so they are cleared before it is stored or looked up.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ReadFields {
+    heart_and_soul: bool,
+    trained_perfection: bool,
+    great_strides: bool,
+    innovation: bool,
+    combo: bool,
+    first_step: bool,
+    quick_innovation: bool,
+    expedience: bool,
+    tricks: bool
+}
+
+impl ReadFields {
+    fn new(available: u64, conditions: ConditionTable) -> ReadFields {
+        // Good-condition actions can't be used when the condition never changes
+        let read = |reads: fn(&Action) -> bool| ACTIONS.iter().enumerate().any(|(method, act)| available & (1 << method) != 0
+            && (act.requirement != Requirement::GoodCondition || conditions != ConditionTable::NormalOnly) && reads(act));
+        ReadFields {
+            heart_and_soul: read(|act| act.requirement == Requirement::HeartAndSoul),
+            trained_perfection: read(|act| act.requirement == Requirement::TrainedPerfection),
+            great_strides: read(|act| matches!(act.effect, Effect::GreatStrides(_))),
+            innovation: read(|act| matches!(act.effect, Effect::Innovation(_) | Effect::QuickInnovation)),
+            combo: read(|act| act.combo.is_some() || matches!(act.requirement, Requirement::Combo(_))),
+            first_step: read(|act| act.requirement == Requirement::FirstStep),
+            quick_innovation: read(|act| act.requirement == Requirement::QuickInnovation),
+            expedience: read(|act| act.requirement == Requirement::Expedience),
+            tricks: read(|act| matches!(act.effect, Effect::RestoreCp(_)))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Store {
+    Hashed(Vec<HashMap<u64, u64>>),
+    Dense(Box<DenseStore>)
+}
+
+/// A non-dominated (quality, CP spent, time spent) outcome; `DPCache::rotation(&start)` reconstructs it.
+#[derive(Debug, Clone, Copy)]
+pub struct FrontierPoint {
+    pub quality: u16,
+    pub cp: u16,
+    pub time: u8,
+    pub start: State
+}
+
+impl FrontierPoint {
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.quality >= other.quality && self.cp <= other.cp && self.time <= other.time
+            && (self.quality > other.quality || self.cp < other.cp || self.time < other.time)
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DPCache {
-    cache: Vec<HashMap<u64, u64>>,
+    cache: Store,
     pub hits: u64,
     pub items: u64,
     check_time: bool,
-    max_dur: u8
+    max_dur: u8,
+    max_cp: u16,
+    conditions: ConditionTable,
+    #[serde(skip)]
+    pub iterative: bool,
+    pub assume_success: bool,
+    pub tricks: u8, // Tricks of the Trade uses a start state gets without a step budget
+    available: u64,
+    read: ReadFields,
+    cheapest: u16, // least CP an available action can cost
+    max_progress: u16, // 0 outside the unified solver
+    pub prog_unit: u16, // progress per 100% efficiency, for the unified solver
+    // quality a failed craft counts as losing; None rules out any chance of failing
+    pub failure_penalty: Option<f64>
 }
 
+const COMPLETION_SCALE: f64 = ((1u64 << 40) - 1) as f64;
 
-pub fn pack_method(quality: u16, method: u8, state: &State, check_time: bool) -> u64 {
-    ((quality as u64) << 48) + ((method as u64) << 40) + state.index(check_time)
+/// The low bits hold the chance that the craft completes.
+pub fn pack_method(quality: u16, method: u8, completion: f64) -> u64 {
+    ((quality as u64) << 48) + ((method as u64) << 40) + (completion * COMPLETION_SCALE).round() as u64
 }
 
-pub fn unpack_method(packed_result: u64) -> (u16, u8, u64) {
-    // quality, method, state
-    ((packed_result >> 48) as u16, (packed_result >> 40) as u8, packed_result & ((1 << 40) - 1))
+pub fn unpack_method(packed_result: u64) -> (u16, u8, f64) {
+    // quality, method, completion chance
+    ((packed_result >> 48) as u16, (packed_result >> 40) as u8, (packed_result & ((1 << 40) - 1)) as f64 / COMPLETION_SCALE)
 }
 
 pub fn apply_igs(quality: u16, innovation: u8, great_strides: u8, inner_quiet: u8) -> u16 {
     quality * (10 + inner_quiet as u16) / 20 * (2 + (if innovation > 0 {1} else {0}) + (if great_strides > 0 {2} else {0}))
 }
 
-pub fn calculate_dur_cost(perstep: u8, steps: u8, delay: u8, wn: u8, manip: u8) -> i8 {
-    if steps == 0 {return 0}
-    let wn = max(wn, delay) - delay;
+pub static UNIT: u16 = 400;
 
-    (perstep*steps) as i8 - (min(manip, steps+delay-1) as i8) - (min(wn, steps)*perstep/2) as i8
+/// Smallest value in `lo..=hi` satisfying a monotone `pred`, assuming `pred(hi)` holds.
+fn lowest(mut lo: u16, mut hi: u16, mut pred: impl FnMut(u16) -> bool) -> u16 {
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if pred(mid) {hi = mid;} else {lo = mid + 1;}
+    }
+    hi
 }
 
-pub static UNIT: u16 = 400;
+/// A single in-game step of a solver action.
+/// Efficiency and progress are in percent; durability is in units of 5.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub name: &'static str,
+    pub wait: u8,
+    pub progress: u16,
+    pub efficiency: u16,
+    pub iq_efficiency: u16, // extra efficiency per Inner Quiet stack
+    pub inner_quiet: u8,
+    pub durability: u8,
+    pub short_halves: bool, // efficiency halves when durability is below the cost
+    pub ticks: bool // running buffs count the step
+}
+
+impl Step {
+    pub const fn touch(name: &'static str, efficiency: u16, inner_quiet: u8, durability: u8) -> Step {
+        Step { name, wait: 3, progress: 0, efficiency, iq_efficiency: 0, inner_quiet, durability, short_halves: false, ticks: true }
+    }
+
+    pub const fn synth(name: &'static str, progress: u16, durability: u8) -> Step {
+        Step { name, wait: 3, progress, efficiency: 0, iq_efficiency: 0, inner_quiet: 0, durability, short_halves: false, ticks: true }
+    }
+
+    pub const fn halved_when_short(self) -> Step {
+        Step { short_halves: true, ..self }
+    }
+
+    /// A step running buffs don't count, like Final Appraisal, Heart and Soul and Quick Innovation.
+    pub const fn untimed(self) -> Step {
+        Step { ticks: false, ..self }
+    }
+
+    pub const fn buff(name: &'static str) -> Step {
+        Step { name, wait: 2, progress: 0, efficiency: 0, iq_efficiency: 0, inner_quiet: 0, durability: 0, short_halves: false, ticks: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    None,
+    WasteNot(u8),
+    Manipulation(u8),
+    Innovation(u8),
+    GreatStrides(u8),
+    Repair(u8),
+    FullRepair,
+    RestoreCp(u16),
+    TrainedPerfection,
+    ConsumeInnerQuiet,
+    ConsumeHeartAndSoul,
+    QuickInnovation,
+    Expedience,
+    Veneration(u8),
+    MuscleMemory(u8),
+    FinalAppraisal
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requirement {
+    None,
+    Unavailable,
+    InnerQuiet,
+    FullInnerQuiet,
+    NoWasteNot,
+    HeartAndSoul,
+    TrainedPerfection,
+    GoodCondition,
+    Combo(Combo),
+    FirstStep,
+    QuickInnovation,
+    Expedience,
+    NoFinalAppraisal
+}
 
-pub static ACTION_NAMES: [&str; 23] = ["(finished)", "",
-    "Basic Touch", "Standard Touch", "Advanced Touch", "Basic+Standard", "Advanced Combo", 
-    "Focused Touch", "Prudent Touch", "Preparatory Touch", "Trained Finesse", "Waste Not I", 
-    "Waste Not II", "Manipulation", "Master's Mend", "Innovation", "Great Strides", 
-    "Observe", "Byregot's", "Precise Touch", "Basic+Refined", "Immaculate Mend", "Trained Perfection"];
+/// Discounted CP and extra Inner Quiet for an action used right after one of `after`,
+/// and the combo it then continues into.
+#[derive(Debug, Clone, Copy)]
+pub struct ComboBonus {
+    pub after: &'static [Combo],
+    pub cp: u16,
+    pub inner_quiet: u8,
+    pub then: Combo
+}
+
+/// A solver action, possibly made of several fused steps.
+/// Buffs tick once per step, and `effect` is applied after the last step.
+/// Any action breaks the current combo unless it continues it.
+#[derive(Debug, Clone, Copy)]
+pub struct Action {
+    pub name: &'static str,
+    pub cp: u16,
+    pub time: u8,
+    pub steps: &'static [Step],
+    pub effect: Effect,
+    pub requirement: Requirement,
+    pub starts: Combo,
+    pub combo: Option<ComboBonus>,
+    pub success: u8, // percent
+    pub level: u8,
+    pub superseded: u8 // level at which a trait replaces this version
+}
+
+impl Action {
+    pub const fn new(name: &'static str, cp: u16, time: u8, steps: &'static [Step], effect: Effect, requirement: Requirement) -> Action {
+        Action { name, cp, time, steps, effect, requirement, starts: Combo::None, combo: None, success: 100, level: 1, superseded: u8::MAX }
+    }
+
+    pub const fn learned(self, level: u8) -> Action {
+        Action { level, ..self }
+    }
+
+    pub const fn superseded_at(self, superseded: u8) -> Action {
+        Action { superseded, ..self }
+    }
+
+    pub const fn chance(self, success: u8) -> Action {
+        Action { success, ..self }
+    }
+
+    pub const fn starts(self, starts: Combo) -> Action {
+        Action { starts, ..self }
+    }
+
+    pub const fn combo(self, after: &'static [Combo], cp: u16, inner_quiet: u8, then: Combo) -> Action {
+        Action { combo: Some(ComboBonus { after, cp, inner_quiet, then }), ..self }
+    }
+
+    pub fn is_synthesis(&self) -> bool {
+        self.steps.iter().any(|step| step.progress > 0)
+    }
+
+    /// Chance in percent that the action succeeds under `condition`; Centered adds 25.
+    pub fn success_chance(&self, condition: Condition) -> u8 {
+        let bonus = if condition == Condition::Centered {25} else {0};
+        min(self.success + bonus, 100)
+    }
+
+    /// The combo bonus that applies after `previous`, if any.
+    pub fn bonus(&self, previous: Combo) -> Option<&ComboBonus> {
+        self.combo.as_ref().filter(|bonus| bonus.after.contains(&previous))
+    }
+}
+
+pub const ACTION_COUNT: usize = 45;
+
+// Indexed by method id; ids are stored in caches, so only append.
+pub const ACTIONS: [Action; ACTION_COUNT] = [
+    Action::new("(finished)", 0, 0, &[], Effect::None, Requirement::Unavailable),
+    Action::new("Basic Touch", 18, 3, &[Step::touch("Basic Touch", 100, 1, 2)], Effect::None, Requirement::None)
+        .starts(Combo::BasicTouch).learned(5),
+    Action::new("Standard Touch", 32, 3, &[Step::touch("Standard Touch", 125, 1, 2)], Effect::None, Requirement::None)
+        .combo(&[Combo::BasicTouch], 18, 0, Combo::StandardTouch).learned(18),
+    Action::new("Advanced Touch", 46, 3, &[Step::touch("Advanced Touch", 150, 1, 2)], Effect::None, Requirement::None)
+        .combo(&[Combo::StandardTouch, Combo::Observe], 18, 0, Combo::None).learned(84),
+    // Fused combos, superseded by combo tracking.
+    Action::new("Basic+Standard", 36, 6, &[], Effect::None, Requirement::Unavailable),
+    Action::new("Advanced Combo", 54, 9, &[], Effect::None, Requirement::Unavailable),
+    Action::new("Focused Touch", 18, 3, &[Step::touch("Focused Touch", 150, 1, 2)], Effect::None, Requirement::Combo(Combo::Observe)).learned(68),
+    Action::new("Prudent Touch", 25, 3, &[Step::touch("Prudent Touch", 100, 1, 1)], Effect::None, Requirement::NoWasteNot).learned(66),
+    Action::new("Preparatory Touch", 40, 3, &[Step::touch("Preparatory Touch", 200, 2, 4)], Effect::None, Requirement::None).learned(71),
+    Action::new("Trained Finesse", 32, 3, &[Step::touch("Trained Finesse", 100, 0, 0)], Effect::None, Requirement::FullInnerQuiet).learned(90),
+    Action::new("Waste Not I", 56, 2, &[Step::buff("Waste Not")], Effect::WasteNot(4), Requirement::None).learned(15),
+    Action::new("Waste Not II", 98, 2, &[Step::buff("Waste Not II")], Effect::WasteNot(8), Requirement::None).learned(47),
+    Action::new("Manipulation", 96, 2, &[Step::buff("Manipulation")], Effect::Manipulation(8), Requirement::None).learned(65),
+    Action::new("Master's Mend", 88, 2, &[Step::buff("Master's Mend")], Effect::Repair(6), Requirement::None).learned(7),
+    Action::new("Innovation", 18, 2, &[Step::buff("Innovation")], Effect::Innovation(4), Requirement::None).learned(26),
+    Action::new("Great Strides", 32, 2, &[Step::buff("Great Strides")], Effect::GreatStrides(3), Requirement::None).learned(21),
+    Action::new("Observe", 7, 3, &[Step { name: "Observe", wait: 3, progress: 0, efficiency: 0, iq_efficiency: 0, inner_quiet: 0, durability: 0, short_halves: false, ticks: true }],
+        Effect::None, Requirement::None).starts(Combo::Observe).learned(13),
+    Action::new("Byregot's", 24, 3, &[Step { name: "Byregot's Blessing", wait: 3, progress: 0, efficiency: 100, iq_efficiency: 20, inner_quiet: 0, durability: 2, short_halves: false, ticks: true }],
+        Effect::ConsumeInnerQuiet, Requirement::InnerQuiet).learned(50),
+    Action::new("Precise Touch", 18, 3, &[Step::buff("Heart and Soul").untimed(),
+        Step::touch("Precise Touch", 200, 2, 2)], Effect::ConsumeHeartAndSoul, Requirement::HeartAndSoul).learned(86),
+    Action::new("Refined Touch", 24, 3, &[Step::touch("Refined Touch", 100, 1, 2)], Effect::None, Requirement::None)
+        .combo(&[Combo::BasicTouch], 24, 1, Combo::None).learned(92),
+    Action::new("Immaculate Mend", 112, 2, &[Step::buff("Immaculate Mend")], Effect::FullRepair, Requirement::None).learned(98),
+    Action::new("Trained Perfection", 0, 2, &[Step::buff("Trained Perfection")], Effect::TrainedPerfection, Requirement::TrainedPerfection).learned(100),
+    Action::new("Tricks of the Trade", 0, 2, &[Step::buff("Tricks of the Trade")], Effect::RestoreCp(20), Requirement::GoodCondition).learned(13),
+    Action::new("Precise Touch (Good)", 18, 3, &[Step::touch("Precise Touch", 200, 2, 2)], Effect::None, Requirement::GoodCondition).learned(53),
+    Action::new("Reflect", 18, 3, &[Step::touch("Reflect", 100, 2, 2)], Effect::None, Requirement::FirstStep).learned(69),
+    Action::new("Quick Innovation", 0, 2, &[Step::buff("Quick Innovation").untimed()], Effect::QuickInnovation, Requirement::QuickInnovation).learned(96),
+    Action::new("Hasty Touch", 0, 3, &[Step::touch("Hasty Touch", 100, 1, 2)], Effect::Expedience, Requirement::None).chance(60).learned(9),
+    Action::new("Daring Touch", 0, 3, &[Step::touch("Daring Touch", 150, 1, 2)], Effect::None, Requirement::Expedience).chance(60).learned(96),
+    // Progress actions, only offered by the unified solver
+    Action::new("Basic Synthesis", 0, 3, &[Step::synth("Basic Synthesis", 100, 2)], Effect::None, Requirement::None).superseded_at(31),
+    Action::new("Basic Synthesis", 0, 3, &[Step::synth("Basic Synthesis", 120, 2)], Effect::None, Requirement::None).learned(31),
+    Action::new("Careful Synthesis", 7, 3, &[Step::synth("Careful Synthesis", 150, 2)], Effect::None, Requirement::None).learned(62).superseded_at(82),
+    Action::new("Careful Synthesis", 7, 3, &[Step::synth("Careful Synthesis", 180, 2)], Effect::None, Requirement::None).learned(82),
+    Action::new("Groundwork", 18, 3, &[Step::synth("Groundwork", 300, 4).halved_when_short()], Effect::None, Requirement::None).learned(72).superseded_at(86),
+    Action::new("Groundwork", 18, 3, &[Step::synth("Groundwork", 360, 4).halved_when_short()], Effect::None, Requirement::None).learned(86),
+    Action::new("Prudent Synthesis", 18, 3, &[Step::synth("Prudent Synthesis", 180, 1)], Effect::None, Requirement::NoWasteNot).learned(88),
+    Action::new("Focused Synthesis", 5, 3, &[Step::synth("Focused Synthesis", 200, 2)], Effect::None, Requirement::Combo(Combo::Observe)).learned(67),
+    Action::new("Intensive Synthesis", 6, 3, &[Step::buff("Heart and Soul").untimed(),
+        Step::synth("Intensive Synthesis", 400, 2)], Effect::ConsumeHeartAndSoul, Requirement::HeartAndSoul).learned(86),
+    Action::new("Intensive Synthesis (Good)", 6, 3, &[Step::synth("Intensive Synthesis", 400, 2)], Effect::None, Requirement::GoodCondition).learned(78),
+    Action::new("Muscle Memory", 6, 3, &[Step::synth("Muscle Memory", 300, 2)], Effect::MuscleMemory(5), Requirement::FirstStep).learned(54),
+    Action::new("Veneration", 18, 2, &[Step::buff("Veneration")], Effect::Veneration(4), Requirement::None).learned(15),
+    // lasts five steps, or until it holds back a step that would complete the craft
+    Action::new("Final Appraisal", 1, 2, &[Step::buff("Final Appraisal").untimed()], Effect::FinalAppraisal, Requirement::NoFinalAppraisal).learned(42),
+    Action::new("Delicate Synthesis", 32, 3, &[Step { name: "Delicate Synthesis", wait: 3, progress: 100, efficiency: 100, iq_efficiency: 0, inner_quiet: 1, durability: 2, short_halves: false, ticks: true }],
+        Effect::None, Requirement::None).learned(76).superseded_at(94),
+    Action::new("Delicate Synthesis", 32, 3, &[Step { name: "Delicate Synthesis", wait: 3, progress: 150, efficiency: 100, iq_efficiency: 0, inner_quiet: 1, durability: 2, short_halves: false, ticks: true }],
+        Effect::None, Requirement::None).learned(94),
+    Action::new("Rapid Synthesis", 0, 3, &[Step::synth("Rapid Synthesis", 250, 2)], Effect::None, Requirement::None).chance(50).learned(9).superseded_at(63),
+    Action::new("Rapid Synthesis", 0, 3, &[Step::synth("Rapid Synthesis", 500, 2)], Effect::None, Requirement::None).chance(50).learned(63),
+];
+
+/// Bitmask over method ids of the actions learned by crafter level `level`.
+/// Progress actions, Delicate Synthesis among them, and Final Appraisal are only included for the unified solver:
+/// the split solver's quality phase tracks no progress, so there they are left to the progress search. The Heart and
+/// Soul actions are only included if the crafter has it.
+pub fn action_mask(level: u8, unified: bool, has: bool) -> u64 {
+    ACTIONS.iter().enumerate()
+        .filter(|(_, act)| act.level <= level && level < act.superseded)
+        .filter(|(_, act)| has || act.requirement != Requirement::HeartAndSoul)
+        .filter(|(_, act)| unified || !(act.is_synthesis() || act.effect == Effect::FinalAppraisal))
+        .fold(0, |mask, (method, _)| mask | (1 << method))
+}
+
+/// The version of the action called `name` that a crafter of level `level` has, or None if it isn't learned yet.
+/// The progress simulator checks its actions against these levels too.
+pub fn learned_version(name: &str, level: u8) -> Option<&'static Action> {
+    ACTIONS.iter().find(|act| act.name == name && act.level <= level && level < act.superseded)
+}
+
+/// Level the action called `name` is first learned at.
+pub fn learned_at(name: &str) -> u8 {
+    ACTIONS.iter().filter(|act| act.name == name).map(|act| act.level).min().unwrap_or(u8::MAX)
+}
+
+/// Least CP any action in `available` can cost, with its combo discount and, if Pliant can roll, halved.
+/// Zero if a free action is available, so that running out of CP alone never ends the search.
+fn cheapest(available: u64, conditions: ConditionTable) -> u16 {
+    ACTIONS.iter().enumerate()
+        .filter(|(method, act)| available & (1 << method) != 0 && act.requirement != Requirement::Unavailable)
+        .map(|(_, act)| act.combo.map_or(act.cp, |bonus| min(act.cp, bonus.cp)))
+        .map(|cp| if conditions == ConditionTable::Expert {cp - cp / 2} else {cp})
+        .min().unwrap_or(u16::MAX)
+}
+
+pub const ACTION_NAMES: [&str; ACTION_COUNT] = {
+    let mut names = [""; ACTION_COUNT];
+    let mut i = 0;
+    while i < ACTION_COUNT {
+        names[i] = ACTIONS[i].name;
+        i += 1;
+    }
+    names
+};
 
 impl DPCache {
-    pub fn new(max_dur: u8, check_time: bool) -> DPCache {
+    /// `max_progress` is the progress the unified solver has to reach, or 0 for the quality phase alone.
+    pub fn new(max_dur: u8, max_cp: u16, check_time: bool, conditions: ConditionTable, max_progress: u16) -> DPCache {
         let mut caches: Vec<HashMap<u64, u64>> = Vec::new();
-        for _ in 0..120 { 
+        // one map per slot and per value of the progress bits the packed state has no room for
+        for _ in 0..SLOTS * (State::progress_beside(max_progress) + 1) {
             caches.push(HashMap::new());
         }
         DPCache {
-            cache: caches,
+            cache: Store::Hashed(caches),
             hits: 0,
             items: 0,
             check_time,
-            max_dur
+            max_dur,
+            max_cp,
+            conditions,
+            iterative: false,
+            assume_success: false,
+            tricks: TRICKS_WITHOUT_TIME,
+            available: u64::MAX,
+            read: ReadFields::new(u64::MAX, conditions),
+            cheapest: cheapest(u64::MAX, conditions),
+            max_progress,
+            prog_unit: 0,
+            failure_penalty: None
         }
     }
 
-    pub fn get(&self, time: u8, index: u64) -> Option<&u64> {
-        self.cache[if self.check_time {time as usize} else {0}].get(&index)
+    /// Restricts the solver to the actions in the `action_mask` bitmask `available`.
+    pub fn set_available(&mut self, available: u64) {
+        self.available = available;
+        self.read = ReadFields::new(available, self.conditions);
+        self.cheapest = cheapest(available, self.conditions);
     }
 
-    pub fn get_state(&self, state: &State) -> Option<&u64> {
-        self.cache[if self.check_time {state.time as usize} else {0}].get(&state.index(false))
+    /// `state` with the fields the cache's actions can't read cleared, as it is stored.
+    fn key(&self, state: &State) -> State {
+        let read = &self.read;
+        let unified = self.max_progress > 0;
+        State {
+            heart_and_soul: state.heart_and_soul && read.heart_and_soul,
+            trained_perfection: if read.trained_perfection {state.trained_perfection} else {Perfection::Available},
+            min_durability: if unified {0} else {state.min_durability},
+            great_strides: if read.great_strides {state.great_strides} else {0},
+            innovation: if read.innovation {state.innovation} else {0},
+            condition: if self.conditions == ConditionTable::NormalOnly {Condition::Normal} else {state.condition},
+            combo: if read.combo {state.combo} else {Combo::None},
+            first_step: state.first_step && read.first_step,
+            quick_innovation: state.quick_innovation && read.quick_innovation,
+            expedience: state.expedience && read.expedience,
+            // outside the unified solver, Veneration only matters to a finisher taking it over
+            veneration: if unified || state.carry == Carry::Veneration {state.veneration} else {0},
+            tricks: if read.tricks {state.tricks} else {0},
+            ..*state
+        }
     }
 
-    pub fn insert(&mut self, time: u8, index: u64, value: u64) -> Option<u64> {
-        self.cache[if self.check_time {time as usize} else {0}].insert(index, value)
+    /// Switches an empty cache to a `DenseStore` sized for every state it can reach.
+    pub fn make_dense(&mut self, carried: &[Carry], max_slots: u64) -> Result<(), String> {
+        self.cache = Store::Dense(Box::new(DenseStore::new(self, carried, max_slots)?));
+        Ok(())
+    }
+
+    /// Part of the key stored beside the packed state: the time with a step budget, else the Tricks of the Trade uses left.
+    fn slot(&self, state: &State) -> u8 {
+        if self.check_time {state.time} else {state.tricks}
+    }
+
+    /// Map of the hashed store a state goes in.
+    fn bucket(state: &State, slot: u8) -> usize {
+        slot as usize + SLOTS * State::progress_beside(state.progress)
+    }
+
+    pub fn get_state(&self, state: &State) -> Option<u64> {
+        let state = self.key(state);
+        let slot = self.slot(&state);
+        match &self.cache {
+            Store::Hashed(cache) => cache[Self::bucket(&state, slot)].get(&state.index()).copied(),
+            Store::Dense(store) => store.get(&state, slot)
+        }
     }
 
     pub fn insert_state(&mut self, state: &State, value: u64) -> Option<u64> {
-        self.cache[if self.check_time {state.time as usize} else {0}].insert(state.index(false), value)
+        let state = self.key(state);
+        let slot = self.slot(&state);
+        match &mut self.cache {
+            Store::Hashed(cache) => cache[Self::bucket(&state, slot)].insert(state.index(), value),
+            Store::Dense(store) => store.insert(&state, slot, value)
+        }
     }
 
 
     pub fn check(&self, state: &State) -> Option<u64> {
-        self.get_state(state).and_then(|x| Some(*x))
+        self.get_state(state)
+    }
+
+    /// Applies `act` to `state`, returning the resulting state and the quality gained,
+    /// or None if the action cannot be used. The current condition only affects the first step,
+    /// and the returned state carries the most likely next condition.
+    pub fn apply(&self, state: &State, act: &Action) -> Option<(State, u16)> {
+        self.apply_outcome(state, act, true)
+    }
+
+    /// Like `apply`, for a chance-based action that succeeded or failed.
+    /// A failed touch costs its CP and durability but adds no quality or Inner Quiet.
+    pub fn apply_outcome(&self, state: &State, act: &Action, success: bool) -> Option<(State, u16)> {
+        let usable = match act.requirement {
+            Requirement::None => true,
+            Requirement::Unavailable => false,
+            Requirement::InnerQuiet => state.inner_quiet > 0,
+            Requirement::FullInnerQuiet => state.inner_quiet == 10,
+            Requirement::NoWasteNot => state.waste_not == 0,
+            Requirement::HeartAndSoul => state.heart_and_soul,
+            Requirement::TrainedPerfection => state.trained_perfection == Perfection::Available,
+            Requirement::GoodCondition => matches!(state.condition, Condition::Good | Condition::Excellent),
+            Requirement::Combo(combo) => state.combo == combo,
+            Requirement::FirstStep => state.first_step,
+            Requirement::QuickInnovation => state.quick_innovation && state.innovation == 0,
+            Requirement::Expedience => state.expedience,
+            Requirement::NoFinalAppraisal => state.final_appraisal == 0
+        };
+        if matches!(act.effect, Effect::RestoreCp(_)) && !self.check_time && state.tricks == 0 {
+            // without a step budget, restoring CP could loop forever, so the uses are counted instead
+            return None;
+        }
+        let condition = state.condition;
+        let bonus = act.bonus(state.combo);
+        let base_cp = bonus.map_or(act.cp, |bonus| bonus.cp);
+        let cp_cost = if condition == Condition::Pliant {base_cp - base_cp / 2} else {base_cp};
+        if !usable || state.cp < cp_cost || (self.check_time && state.time < act.time) {
+            return None;
+        }
+        let mut st = *state;
+        st.cp -= cp_cost;
+        st.time = if self.check_time {st.time - act.time} else {0};
+        st.condition = self.conditions.transitions(condition)[0].0;
+        st.first_step = false;
+        st.expedience = false;
+        // in the split solver, first-step actions are played before the progress opener, so its buffs don't apply to them
+        let opening = act.requirement == Requirement::FirstStep;
+        let mut qual = 0;
+        for (i, step) in act.steps.iter().enumerate() {
+            let mut short = false;
+            if step.durability > 0 {
+                let mut cost = step.durability;
+                if st.waste_not > 0 && !opening {cost -= cost / 2;}
+                if i == 0 && condition == Condition::Sturdy {cost -= cost / 2;}
+                if st.trained_perfection == Perfection::Active {
+                    st.trained_perfection = Perfection::Used;
+                    cost = 0;
+                }
+                // in the split solver the finisher still has to be played, so the craft can't reach zero
+                if st.durability <= cost && self.max_progress == 0 {
+                    return None;
+                }
+                short = st.durability < cost;
+                // in the unified solver, running out is only a failure if progress is incomplete (checked below)
+                st.durability = st.durability.saturating_sub(cost);
+            }
+            if step.progress > 0 && success {
+                let efficiency = if short && step.short_halves {step.progress / 2} else {step.progress};
+                let mut buffs = 100;
+                if st.veneration > 0 {buffs += 50;}
+                if st.muscle_memory > 0 {
+                    buffs += 100;
+                    st.muscle_memory = 0;
+                }
+                let malleable = if i == 0 && condition == Condition::Malleable {150} else {100};
+                // rounded down once, as the game does
+                let progress = self.prog_unit as u32 * efficiency as u32 * buffs * malleable / 1_000_000;
+                let left = st.progress.saturating_sub(min(progress, u16::MAX as u32) as u16);
+                if left == 0 && st.final_appraisal > 0 {
+                    // Final Appraisal holds the craft one short of completion, and is used up
+                    st.progress = 1;
+                    st.final_appraisal = 0;
+                } else {
+                    st.progress = left;
+                }
+            }
+            if self.max_progress > 0 && st.progress > 0 && st.durability == 0 {
+                // the craft breaks before it completes
+                return None;
+            }
+            if step.efficiency > 0 && success {
+                let efficiency = step.efficiency + step.iq_efficiency * st.inner_quiet as u16;
+                // efficiencies past 163% overflow u16 before the division
+                let base = (UNIT as u32 * efficiency as u32 / 100) as u16;
+                let step_qual = apply_igs(base, st.innovation, st.great_strides, st.inner_quiet);
+                qual += match (i, condition) {
+                    (0, Condition::Good) => step_qual * 3 / 2,
+                    (0, Condition::Excellent) => step_qual * 4,
+                    (0, Condition::Poor) => step_qual / 2,
+                    _ => step_qual
+                };
+                st.great_strides = 0;
+            }
+            if success {
+                st.inner_quiet = min(st.inner_quiet + step.inner_quiet, 10);
+            }
+            if opening || !step.ticks {
+                continue;
+            }
+            if st.manipulation > 0 && !matches!(act.effect, Effect::Manipulation(_)) {
+                st.manipulation -= 1;
+                st.durability = min(st.durability + 1, self.max_dur);
+            }
+            st.waste_not = max(st.waste_not, 1) - 1;
+            st.innovation = max(st.innovation, 1) - 1;
+            st.great_strides = max(st.great_strides, 1) - 1;
+            st.veneration = max(st.veneration, 1) - 1;
+            st.muscle_memory = max(st.muscle_memory, 1) - 1;
+            st.final_appraisal = max(st.final_appraisal, 1) - 1;
+        }
+        if let Some(bonus) = bonus.filter(|_| success) {
+            st.inner_quiet = min(st.inner_quiet + bonus.inner_quiet, 10);
+        }
+        st.combo = bonus.map_or(act.starts, |bonus| bonus.then);
+        let primed = if condition == Condition::Primed {2} else {0};
+        match act.effect {
+            Effect::None => {}
+            Effect::WasteNot(n) => {st.waste_not = n + primed;}
+            Effect::Manipulation(n) => {st.manipulation = n + primed;}
+            Effect::Innovation(n) => {st.innovation = n + primed;}
+            Effect::GreatStrides(n) => {st.great_strides = n + primed;}
+            Effect::Repair(n) => {st.durability = min(st.durability + n, self.max_dur);}
+            Effect::FullRepair => {st.durability = self.max_dur;}
+            Effect::RestoreCp(n) => {
+                st.cp = min(st.cp + n, self.max_cp);
+                if !self.check_time {st.tricks -= 1;}
+            }
+            Effect::TrainedPerfection => {st.trained_perfection = Perfection::Active;}
+            Effect::ConsumeInnerQuiet => {st.inner_quiet = 0;}
+            Effect::ConsumeHeartAndSoul => {st.heart_and_soul = false;}
+            Effect::QuickInnovation => {
+                st.innovation = 1;
+                st.quick_innovation = false;
+            }
+            Effect::Expedience => {st.expedience = success;}
+            Effect::Veneration(n) => {st.veneration = n + primed;}
+            Effect::MuscleMemory(n) => {st.muscle_memory = n + primed;}
+            Effect::FinalAppraisal => {st.final_appraisal = 5 + primed;}
+        }
+        Some((st, qual))
     }
 
     pub fn query(&mut self, state: &State) -> Option<NonZero<u64>> {
-        let index = state.index(self.check_time);
+        if self.iterative {
+            self.query_iterative(state)
+        } else {
+            self.query_recursive(state)
+        }
+    }
+
+    /// Returns the value of `state` if it is cached or needs no evaluation.
+    fn settled(&self, state: &State) -> Option<Option<NonZero<u64>>> {
+        if let Some(ret) = self.get_state(state) {
+            return Some(NonZeroU64::new(ret));
+        }
+        if self.max_progress > 0 {
+            // unified solver: the craft ends when progress completes, and must complete in time
+            if state.progress == 0 {
+                return Some(NonZeroU64::new(pack_method(0, 0, 1.)));
+            }
+            if state.time < 2 && self.check_time {
+                return Some(None);
+            }
+            return None;
+        }
+        if state.cp < self.cheapest || (state.time < 2 && self.check_time) {
+            return Some(if state.can_finish() {NonZeroU64::new(pack_method(0, 0, 1.))} else {None});
+        }
+        None
+    }
+
+    /// Every state `state` can move to, over all usable actions and rolled conditions.
+    fn successors(&self, state: &State) -> Vec<State> {
+        let mut states = Vec::new();
+        for (method, act) in ACTIONS.iter().enumerate() {
+            if self.available & (1 << method) == 0 {
+                continue;
+            }
+            for (new_state, _, _) in self.outcomes(state, act) {
+                for &(condition, _) in self.conditions.transitions(state.condition) {
+                    states.push(State {condition, ..new_state});
+                }
+            }
+        }
+        states
+    }
+
+    /// Each way `act` can turn out from `state`, with its quality and probability.
+    /// The successful outcome comes first; failure is only considered unless `assume_success` is set.
+    /// A failure that breaks the craft is left out, so the chances then sum to less than one.
+    fn outcomes(&self, state: &State, act: &Action) -> Vec<(State, u16, f64)> {
+        let Some((new_state, qual)) = self.apply(state, act) else {return Vec::new()};
+        let success = act.success_chance(state.condition);
+        if success >= 100 || self.assume_success {
+            return vec![(new_state, qual, 1.)];
+        }
+        let p = success as f64 / 100.;
+        let mut outcomes = vec![(new_state, qual, p)];
+        if let Some((failed, _)) = self.apply_outcome(state, act, false) {
+            outcomes.push((failed, 0, 1. - p));
+        }
+        outcomes
+    }
+
+    fn query_recursive(&mut self, state: &State) -> Option<NonZero<u64>> {
         self.hits += 1;
-        match self.get_state(state) {
-            Some(ret) => {return NonZeroU64::new(*ret);}
-            None => {self.hits -= 1;}
+        if let Some(ret) = self.settled(state) {
+            return ret;
         }
-        let State {time, inner_quiet, cp, durability, manipulation, 
-            waste_not, innovation, great_strides, min_durability, trained_perfection, heart_and_soul} = *state;
-        if cp < 7 || (time < 2 && self.check_time) { 
-            return if durability < min_durability {None} else {NonZeroU64::new(1)}; 
+        self.hits -= 1;
+        self.evaluate(state)
+    }
+
+    /// Evaluates the DP bottom-up with an explicit stack, so deep caches can be built on normal thread stacks.
+    /// Gives the same results as the recursive evaluation.
+    pub fn query_iterative(&mut self, state: &State) -> Option<NonZero<u64>> {
+        let mut stack = vec![(*state, false)];
+        while let Some((st, expanded)) = stack.pop() {
+            if expanded {
+                // every successor is settled by now, so this does not recurse
+                self.evaluate(&st);
+                continue;
+            }
+            if self.settled(&st).is_some() {
+                continue;
+            }
+            // mark as in progress, matching the recursive re-entry guard
+            self.insert_state(&st, 0);
+            stack.push((st, true));
+            for next in self.successors(&st) {
+                if self.settled(&next).is_none() {
+                    stack.push((next, false));
+                }
+            }
         }
-        //println!("EVAL {} {} {} {} {} {} {} {} {}", time, iq, cp, dur, manip, wn, inno, gs, has);
+        self.query_recursive(state)
+    }
+
+    fn evaluate(&mut self, state: &State) -> Option<NonZero<u64>> {
         self.items += 1;
-        if self.items % 1000000 == 0 {
+        if self.items.is_multiple_of(1000000) {
             println!("Items: {}", self.items);
         }
-        let mut quality_results = [NonZeroU64::new(index); 23];
-        // instantiate with current statenum to preserve information about remaining resources
-        // Basic
-        let action_id: u8 = 1;
-        let raw_dur_cost = 2;
-        let step_count = 1;
-        let delay = 0;
-        let cp_cost = 18;
-        let iq_stacks = 1;
-        let time_cost = 3;
-        let qual_value = UNIT;
-        let scaling = UNIT / 4;
-        let dur_cost = if trained_perfection == 1 {calculate_dur_cost(raw_dur_cost, step_count-delay+1, delay+1, waste_not, manipulation)} 
-            else {calculate_dur_cost(raw_dur_cost, step_count-delay, delay, waste_not, manipulation)};
-        if durability as i8 >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = if manipulation >= step_count + delay {1} else {0};
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Standard
-        let action_id: u8 = 2;
-        let raw_dur_cost = 2;
-        let step_count = 1;
-        let cp_cost = 32;
-        let iq_stacks = 1;
-        let time_cost = 3;
-        let qual_value = UNIT * 5 / 4;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Advanced
-        let action_id: u8 = 3;
-        let raw_dur_cost = 2;
-        let step_count = 1;
-        let cp_cost = 46;
-        let iq_stacks = 1;
-        let time_cost = 3;
-        let qual_value = UNIT * 3 / 2;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Standard Combo
-        let action_id: u8 = 4;
-        let raw_dur_cost = 4;
-        let step_count = 2;
-        let cp_cost = 36;
-        let iq_stacks = 2;
-        let time_cost = 6;
-        let qual_value = UNIT;
-        let dur_cost = if trained_perfection == 1 {raw_dur_cost} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet)
-                + apply_igs(UNIT * 5 / 4, max(innovation, 1) - 1, 0, min(inner_quiet+1, 10));
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Advanced Combo
-        let action_id: u8 = 5;
-        let raw_dur_cost = 6;
-        let step_count = 3;
-        let cp_cost = 54;
-        let iq_stacks = 3;
-        let time_cost = 9;
-        let qual_value = UNIT;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet)
-                + apply_igs(UNIT * 5 / 4, max(innovation, 1) - 1, 0, min(inner_quiet + 1, 10))
-                + apply_igs(UNIT * 3 / 2, max(innovation, 2) - 2, 0, min(inner_quiet + 2, 10));
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Focused Touch
-        let action_id: u8 = 6;
-        let raw_dur_cost = 2;
-        let step_count = 2;
-        let cp_cost = 25;
-        let iq_stacks = 1;
-        let time_cost = 6;
-        let qual_value = UNIT * 3 / 2;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, max(innovation, 1)-1, max(great_strides, 1) - 1, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Prudent Touch
-        let action_id: u8 = 7;
-        let raw_dur_cost = 1;
-        let step_count = 1;
-        let cp_cost = 25;
-        let iq_stacks = 1;
-        let time_cost = 3;
-        let qual_value = UNIT;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Prepratory Touch
-        let action_id: u8 = 8;
-        let raw_dur_cost = 4;
-        let step_count = 1;
-        let cp_cost: u16 = 40;
-        let iq_stacks = 2;
-        let time_cost = 3;
-        let qual_value = UNIT * 2;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Trained Finesse
-        let action_id: u8 = 9;
-        let step_count = 1;
-        let cp_cost = 32;
-        let time_cost = 3;
-        let qual_value = UNIT;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = 0;
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet,
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Waste Not 1
-        let action_id: u8 = 10;
-        let step_count = 1;
-        let cp_cost = 56;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: min(durability + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: 4,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Waste Not 2
-        let action_id: u8 = 11;
-        let step_count = 1;
-        let cp_cost = 98;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: min(durability + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: 8,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Manipulation
-        let action_id: u8 = 12;
-        let step_count = 1;
-        let cp_cost = 96;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability,
-                manipulation: 8,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Master's Mend
-        let action_id: u8 = 13;
-        let step_count = 1;
-        let cp_cost = 88;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: min(durability + 6 + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Innovation
-        let action_id: u8 = 14;
-        let step_count = 1;
-        let cp_cost: u16 = 18;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: min(durability + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: 4,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Great Strides
-        let action_id: u8 = 15;
-        let step_count = 1;
-        let cp_cost = 32;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: min(durability + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 3,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        /* Observe
-        if *cp >= 7 && (!self.check_time || *time >= 2) {
-            let new_state = State {
-                time: if self.check_time {time - 2} else {0}, 
-                iq: *iq, 
-                cp: cp - 7,
-                dur: *dur + min(*manip, 1),
-                manip: max(manip - 1, 0),
-                wn: max(wn - 1, 0),
-                inno: max(inno - 1, 0),
-                gs: max(gs - 1, 0),
-                has: *has
-            };
-            res[16] = combine_method((self.query(&new_state) >> 48) as u16, 16, &new_state, self.check_time);
-        }*/
-        // Byregot's Blessing
-        let action_id: u8 = 17;
-        let raw_dur_cost = 2;
-        let step_count = 1;
-        let cp_cost: u16 = 24;
-        let time_cost = 3;
-        let qual_value = UNIT * (10 + 2 * inner_quiet as u16) / 10;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: 0, 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        // Precise Touch
-        let action_id: u8 = 18;
-        let raw_dur_cost = 2;
-        let step_count = 1;
-        let cp_cost = 18;
-        let iq_stacks = 2;
-        let time_cost = 3;
-        let qual_value = UNIT * 2;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && heart_and_soul && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul: false
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet);
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        let action_id: u8 = 19;
-        let raw_dur_cost = 4;
-        let step_count = 2;
-        let cp_cost = 42;
-        let iq_stacks = 3;
-        let time_cost = 6;
-        let qual_value = UNIT;
-        let dur_cost = if trained_perfection == 1 {0} 
-            else {raw_dur_cost - min(manipulation, step_count-1) - min(waste_not, raw_dur_cost / 2)};
-        if durability >= dur_cost && cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let adjusted_cost = if trained_perfection == 1 {0} else {raw_dur_cost - min(waste_not, raw_dur_cost / 2)};
-            let manip_gain = min(manipulation, step_count);
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet: min(inner_quiet + iq_stacks, 10), 
-                cp: cp - cp_cost,
-                durability: min(durability + manip_gain - adjusted_cost, self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: 0,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let qual = apply_igs(qual_value, innovation, great_strides, inner_quiet)
-                + apply_igs(UNIT, max(innovation, 1) - 1, 0, min(inner_quiet+1, 10));
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16 + qual, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        let action_id: u8 = 20;
-        let step_count = 1;
-        let cp_cost = 112;
-        let time_cost = 2;
-        if cp >= cp_cost && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp: cp - cp_cost,
-                durability: self.max_dur,
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: if trained_perfection > 0 {2} else {0},
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
-        }
-        let action_id: u8 = 21;
-        let step_count = 1;
-        let time_cost = 2;
-        if trained_perfection == 0 && (!self.check_time || time >= time_cost) {
-            let new_state = State {
-                time: if self.check_time {time - time_cost} else {0}, 
-                inner_quiet, 
-                cp,
-                durability: min(durability + min(manipulation, step_count), self.max_dur),
-                manipulation: max(manipulation, step_count) - step_count,
-                waste_not: max(waste_not, step_count) - step_count,
-                innovation: max(innovation, step_count) - step_count,
-                great_strides: max(great_strides, step_count) - step_count,
-                min_durability,
-                trained_perfection: 1,
-                heart_and_soul
-            };
-            let res = self.query(&new_state);
-            quality_results[action_id as usize] = if let Some(res) = res {
-                NonZeroU64::new(pack_method((res.get() >> 48) as u16, action_id, &new_state, self.check_time))
-            } else {None};
+        // mark the state while it is evaluated; re-entry would be a cycle, so it counts as a dead end
+        self.insert_state(state, 0);
+        // best (score, completion chance, packed result) so far; ties keep the earlier action
+        let mut best = if state.can_finish() && state.progress == 0 {Some((0., 1., pack_method(0, 0, 1.)))} else {None};
+        let risky = self.failure_penalty.is_some();
+        'actions: for (method, act) in ACTIONS.iter().enumerate() {
+            if self.available & (1 << method) == 0 {
+                continue;
+            }
+            let outcomes = self.outcomes(state, act);
+            if outcomes.is_empty() || (!risky && outcomes.iter().map(|o| o.2).sum::<f64>() < 1.) {
+                continue;
+            }
+            // maximise expected quality over the action's outcomes and the conditions the next step may roll,
+            // less the penalty for failing; quality only counts if the craft completes
+            let mut expected = 0.;
+            let mut completion = 0.;
+            for (next, qual, chance) in outcomes {
+                for &(condition, p) in self.conditions.transitions(state.condition) {
+                    match self.query_recursive(&State {condition, ..next}) {
+                        Some(res) => {
+                            let (q, _, c) = unpack_method(res.get());
+                            expected += chance * p * (qual as f64 * c + q as f64);
+                            completion += chance * p * c;
+                        }
+                        None if risky => {}
+                        None => {continue 'actions;}
+                    }
+                }
+            }
+            if completion <= 0. {
+                continue;
+            }
+            let qual = expected.round() as u16;
+            let score = qual as f64 - self.failure_penalty.unwrap_or(0.) * (1. - completion);
+            let packed = pack_method(qual, method as u8, completion);
+            if best.is_none_or(|(s, c, _)| (score, completion) > (s, c)) {
+                best = Some((score, completion, packed));
+            }
         }
-        let ret = *quality_results.iter().max().unwrap();
-        self.insert_state(state, if let Some(res) = ret {res.get()} else {0});
-        ret
+        let best = best.and_then(|(_, _, packed)| NonZeroU64::new(packed));
+        self.insert_state(state, if let Some(res) = best {res.get()} else {0});
+        best
+    }
+
+    /// Target-quality objective: among rotations from `state` that reach `target`, prefers the one leaving
+    /// the most CP, then the most durability, then the most time. Returns the reduced start state whose best
+    /// rotation is that one (it stays valid from `state`), or None if `target` is out of reach.
+    pub fn target_state(&mut self, state: &State, target: u16) -> Option<State> {
+        let check_time = self.check_time;
+        let mut reaches = |st: &State| self.query(st).is_some_and(|res| (res.get() >> 48) as u16 >= target);
+        if !reaches(state) {
+            return None;
+        }
+        // quality never drops with more resources, so each can be bisected in turn
+        let mut st = *state;
+        let cp = lowest(0, st.cp, |cp| reaches(&State {cp, ..st}));
+        st.cp = cp;
+        let durability = lowest(st.min_durability as u16, st.durability as u16, |d| reaches(&State {durability: d as u8, ..st}));
+        st.durability = durability as u8;
+        if check_time {
+            let time = lowest(0, st.time as u16, |t| reaches(&State {time: t as u8, ..st}));
+            st.time = time as u8;
+        }
+        Some(st)
+    }
+
+    /// Pareto frontier of quality against CP and time spent from `state`.
+    /// Every CP and time budget up to the state's own is solved, and the resulting rotations are filtered
+    /// down to the non-dominated ones. Needs check_time, as without it rotations that only differ in time
+    /// would be the same point.
+    pub fn frontier(&mut self, state: &State) -> Vec<FrontierPoint> {
+        assert!(self.check_time, "the frontier needs check_time");
+        let mut points: Vec<FrontierPoint> = Vec::new();
+        for cp in 0..=state.cp {
+            for time in 0..=state.time {
+                let start = State {cp, time, ..*state};
+                let Some(res) = self.query(&start) else {continue};
+                let rotation = self.rotation(&start);
+                let end = rotation.last().map_or(start, |(_, next, _)| *next);
+                let point = FrontierPoint {
+                    quality: (res.get() >> 48) as u16,
+                    cp: cp.saturating_sub(end.cp),
+                    time: rotation.iter().map(|(method, _, _)| ACTIONS[*method as usize].time).sum(),
+                    start
+                };
+                if points.iter().any(|p| p.dominates(&point) || (p.quality, p.cp, p.time) == (point.quality, point.cp, point.time)) {
+                    continue;
+                }
+                points.retain(|p| !point.dominates(p));
+                points.push(point);
+            }
+        }
+        points.sort_by_key(|p| (p.cp, p.time));
+        points
     }
 
     pub fn unwrapped_query(&mut self, state: &State) -> u64 {
         if let Some(res) = self.query(state) {res.get()} else {0}
     }
 
-    pub fn get_time(ind: u64) -> u8 {
-        (ind >> 37) as u8
+    /// Follows the cached best methods from `state`, returning each method with the state it leads to
+    /// and the quality it adds.
+    pub fn rotation(&self, state: &State) -> Vec<(u8, State, u16)> {
+        let mut steps = Vec::new();
+        let mut st = *state;
+        let (_, mut method, _) = unpack_method(self.check(&st).unwrap_or(0));
+        while method > 0 {
+            assert!((method as usize) < ACTION_COUNT, "invalid method");
+            let Some((next, qual)) = self.apply(&st, &ACTIONS[method as usize]) else {break};
+            steps.push((method, next, qual));
+            st = next;
+            (_, method, _) = unpack_method(self.check(&st).unwrap_or(0));
+        }
+        steps
     }
 
     pub fn print_backtrace(&self, state: &State) {
-        println!("START {}", State::unpack(state.index(self.check_time)));
-        let mut prev = self.check(state).unwrap_or_else(|| 0);
-        let (mut qual, mut method, mut last) = unpack_method(prev);
-        let mut orig = qual;
+        println!("START {}", state);
+        let (qual, _, _) = unpack_method(self.check(state).unwrap_or(0));
         println!("TOTAL: {:.4}", qual as f64 / 400.0);
-        while method > 0 {
-            assert!(method < 22, "invalid method");
-            prev = match self.get(Self::get_time(last), last & ((1 << 37) - 1)) {None => 0, Some(t) => *t};
-            qual = (prev >> 48) as u16;
-            println!("{:02} {:20} {:.4} {}", method, 
-                ACTION_NAMES[method as usize + 1], 
-                (orig - qual) as f64 / 400.0,
-                State::unpack(last));
-            (orig, method, last) = unpack_method(prev);
+        for (method, next, qual) in self.rotation(state) {
+            println!("{:02} {:20} {:.4} {}", method,
+                ACTION_NAMES[method as usize],
+                qual as f64 / 400.0,
+                next);
         }
         println!("FINISHED");
     }
 
     pub fn print_macro(&self, st: &State) {
-        let mut prev = self.check(st).unwrap_or_else(|| 0);
-        let (_, mut method, mut last) = unpack_method(prev);
-        while method > 0 {
-            assert!(method < 19, "invalid method");
-            prev = match self.get(Self::get_time(last), last & ((1 << 33) - 1)) {None => 0, Some(t) => *t};
-            match method {
-                1 => {println!("/ac \"Basic Touch\" <wait.3>");},
-                2 => {println!("/ac \"Standard Touch\" <wait.3>");},
-                3 => {println!("/ac \"Advanced Touch\" <wait.3>");},
-                4 => {
-                    println!("/ac \"Basic Touch\" <wait.3>");
-                    println!("/ac \"Standard Touch\" <wait.3>");
-                },
-                5 => {
-                    println!("/ac \"Basic Touch\" <wait.3>");
-                    println!("/ac \"Standard Touch\" <wait.3>");
-                    println!("/ac \"Advanced Touch\" <wait.3>");
-                },
-                6 => {
-                    println!("/ac Observe <wait.3>");
-                    println!("/ac \"Focused Touch\" <wait.3>");
-                },
-                7 => {
-                    println!("/ac \"Prudent Touch\" <wait.3>");
-                }
-                8 => {
-                    println!("/ac \"Preparatory Touch\" <wait.3>");
-                },
-                9 => {
-                    println!("/ac \"Trained Finesse\" <wait.3>");
-                },
-                10 => {
-                    println!("/ac \"Waste Not\" <wait.2>");
-                },
-                11 => {
-                    println!("/ac \"Waste Not II\" <wait.2>");
-                },
-                12 => {
-                    println!("/ac \"Manipulation\" <wait.2>");
-                },
-                13 => {
-                    println!("/ac \"Master's Mend\" <wait.2>");
-                },
-                14 => {
-                    println!("/ac Innovation <wait.2>");
-                }
-                15 => {
-                    println!("/ac \"Great Strides\" <wait.2>");
-                }
-                16 => {
-                    println!("/ac Observe <wait.3>");
-                }
-                17 => {
-                    println!("/ac \"Byregot's Blessing\" <wait.3>");
-                }
-                18 => {
-                    println!("/ac \"Heart and Soul\" <wait.3>");
-                    println!("/ac \"Precise Touch\" <wait.3>");
-                }
-                19 => {
-                    println!("/ac \"Basic Touch\" <wait.3>");
-                    println!("/ac \"Refined Touch\" <wait.3>");
-                }
-                20 => {
-                    println!("/ac \"Immaculate Mend\" <wait.2>");
-                }
-                21 => {
-                    println!("/ac \"Trained Perfection\" <wait.2>");
-                }
-                _ => {}
-            }
-            (_, method, last) = unpack_method(prev);
+        for (method, _, _) in self.rotation(st) {
+            print_action(&ACTIONS[method as usize]);
         }
     }
 
+    /// Splits off the cached first-step action from `st`, if the rotation opens with one,
+    /// since it has to be played before the progress opener.
+    pub fn first_step(&self, st: &State) -> Option<(u8, State)> {
+        self.rotation(st).first()
+            .filter(|(method, _, _)| ACTIONS[*method as usize].requirement == Requirement::FirstStep)
+            .map(|(method, next, _)| (*method, *next))
+    }
+
     pub fn check_endstate(&mut self, st: &State) -> State {
-        let mut prev = st.index(self.check_time);
-        let mut curr = self.unwrapped_query(st);
-        let (_qual,  mut method, mut next) = unpack_method(curr);
-        while method > 0 {
-            assert!(method < 22, "invalid method");
-            prev = next;
-            curr = match self.get(Self::get_time(next), next & ((1 << 33) - 1)) {None => 0, Some(t) => *t};
-            (_, method, next) = unpack_method(curr);
+        self.query(st);
+        self.rotation(st).last().map_or(*st, |(_, next, _)| *next)
+    }
+}
+
+pub fn print_action(act: &Action) {
+    for step in act.steps {
+        if step.name.contains(' ') {
+            println!("/ac \"{}\" <wait.{}>", step.name, step.wait);
+        } else {
+            println!("/ac {} <wait.{}>", step.name, step.wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(cp: u16, durability: u8) -> State {
+        State {
+            time: 0,
+            inner_quiet: 0,
+            cp,
+            durability,
+            manipulation: 0,
+            waste_not: 0,
+            innovation: 0,
+            great_strides: 0,
+            min_durability: 0,
+            trained_perfection: Perfection::Available,
+            heart_and_soul: false,
+            condition: Condition::Normal,
+            combo: Combo::None,
+            first_step: false,
+            quick_innovation: false,
+            expedience: false,
+            veneration: 0,
+            muscle_memory: 0,
+            progress: 0,
+            final_appraisal: 0,
+            carry: Carry::None,
+            tricks: 0
+        }
+    }
+
+    fn action(name: &str) -> &'static Action {
+        ACTIONS.iter().rev().find(|act| act.name == name).unwrap()
+    }
+
+    fn cache(max_dur: u8, max_cp: u16) -> DPCache {
+        DPCache::new(max_dur, max_cp, false, ConditionTable::NormalOnly, 0)
+    }
+
+    #[test]
+    fn progress_in_points() {
+        let mut cache = DPCache::new(12, 500, false, ConditionTable::NormalOnly, 1000);
+        cache.prog_unit = 229;
+        let st = State {progress: 1000, ..state(500, 12)};
+        let left = |st: State| cache.apply(&st, action("Careful Synthesis")).unwrap().0.progress;
+        // 412.2, 618.3 and 824.4 points, each rounded down once
+        assert_eq!(left(st), 588);
+        assert_eq!(left(State {veneration: 1, ..st}), 382);
+        assert_eq!(left(State {muscle_memory: 1, ..st}), 176);
+        assert_eq!(left(State {final_appraisal: 5, progress: 400, ..st}), 1);
+        // states that differ only in the progress bits the packed state has no room for are kept apart
+        cache.insert_state(&State {progress: 300, ..st}, 7);
+        assert_eq!(cache.get_state(&State {progress: 300, ..st}), Some(7));
+        assert_eq!(cache.get_state(&State {progress: 44, ..st}), None);
+    }
+
+    #[test]
+    fn final_appraisal_outlasts_quality_steps() {
+        let mut cache = DPCache::new(12, 500, false, ConditionTable::NormalOnly, 400);
+        cache.prog_unit = 229;
+        let st = State {progress: 400, ..state(500, 12)};
+        let (st, _) = cache.apply(&st, action("Final Appraisal")).unwrap();
+        assert_eq!(st.final_appraisal, 5);
+        let (st, quality) = cache.apply(&st, action("Basic Touch")).unwrap();
+        assert_eq!((quality, st.final_appraisal, st.progress), (400, 4, 400));
+        // Careful Synthesis would complete the craft, so it's held one short and Final Appraisal is used up
+        let (st, _) = cache.apply(&st, action("Careful Synthesis")).unwrap();
+        assert_eq!((st.progress, st.final_appraisal), (1, 0));
+        let (st, _) = cache.apply(&st, action("Careful Synthesis")).unwrap();
+        assert_eq!(st.progress, 0);
+        // it wears off after five steps
+        let mut st = cache.apply(&State {progress: 400, ..state(500, 12)}, action("Final Appraisal")).unwrap().0;
+        for _ in 0..5 {
+            st = cache.apply(&st, action("Observe")).unwrap().0;
+        }
+        assert_eq!(st.final_appraisal, 0);
+        assert_eq!(cache.apply(&st, action("Careful Synthesis")).unwrap().0.progress, 0);
+    }
+
+    #[test]
+    fn quality_of_high_efficiency_touches() {
+        let cache = cache(12, 500);
+        let st = state(500, 12);
+        assert_eq!(cache.apply(&st, action("Basic Touch")).unwrap().1, 400);
+        assert_eq!(cache.apply(&st, action("Preparatory Touch")).unwrap().1, 800);
+        assert_eq!(cache.apply(&State {heart_and_soul: true, ..st}, action("Precise Touch")).unwrap().1, 800);
+        // 100% plus 20% efficiency per stack is 300%, and 10 stacks of Inner Quiet at 10% each double it
+        let full = State {inner_quiet: 10, ..st};
+        assert_eq!(cache.apply(&full, action("Byregot's")).unwrap().1, 2400);
+        // Innovation's 50% and Great Strides' 100% add up, for 250%
+        assert_eq!(cache.apply(&State {innovation: 1, great_strides: 1, ..full}, action("Byregot's")).unwrap().1, 6000);
+    }
+
+    #[test]
+    fn split_solver_never_reaches_zero_durability() {
+        let cache = cache(12, 500);
+        assert!(cache.apply(&state(500, 2), action("Basic Touch")).is_none());
+        let (st, _) = cache.apply(&state(500, 3), action("Basic Touch")).unwrap();
+        assert_eq!(st.durability, 1);
+        // Manipulation can't bring a broken craft back either
+        assert!(cache.apply(&State {manipulation: 2, ..state(500, 2)}, action("Basic Touch")).is_none());
+    }
+
+    #[test]
+    fn expected_quality_over_conditions() {
+        // Basic Touch only
+        let mut cache = DPCache::new(5, 36, false, ConditionTable::Standard, 0);
+        cache.set_available(action_mask(5, false, false));
+        // 400 under Normal, then 440 at one stack of Inner Quiet: x1 76%, x1.5 20% and x4 4% of the time
+        let (quality, _, completion) = unpack_method(cache.unwrapped_query(&state(36, 5)));
+        assert_eq!((quality, completion), (937, 1.));
+        // 600 under Good, which is always followed by Normal
+        let (quality, _, _) = unpack_method(cache.unwrapped_query(&State {condition: Condition::Good, ..state(36, 5)}));
+        assert_eq!(quality, 1040);
+    }
+
+    #[test]
+    fn tricks_capped_without_time() {
+        let cache = DPCache::new(5, 100, false, ConditionTable::Standard, 0);
+        let good = State {condition: Condition::Good, ..state(50, 5)};
+        assert!(cache.apply(&good, action("Tricks of the Trade")).is_none());
+        let (st, _) = cache.apply(&State {tricks: 1, ..good}, action("Tricks of the Trade")).unwrap();
+        assert_eq!((st.cp, st.tricks), (70, 0));
+    }
+
+    #[test]
+    fn free_actions_below_seven_cp() {
+        // Basic Touch, Master's Mend and Hasty Touch
+        let mut cache = cache(5, 6);
+        cache.set_available(action_mask(9, false, false));
+        // two Hasty Touches at 60%: 0.6 * (400 + 0.6 * 440) + 0.4 * 0.6 * 400
+        let (quality, method, _) = unpack_method(cache.unwrapped_query(&state(6, 5)));
+        assert_eq!((quality, ACTIONS[method as usize].name), (494, "Hasty Touch"));
+    }
+
+    #[test]
+    fn target_leaves_most_resources() {
+        let mut cache = DPCache::new(5, 54, true, ConditionTable::NormalOnly, 0);
+        cache.set_available(action_mask(5, false, false));
+        let st = State {time: 15, ..state(54, 5)};
+        // two Basic Touches reach 840, with 18 CP and one step of durability to spare
+        let reduced = cache.target_state(&st, 800).unwrap();
+        assert_eq!((reduced.cp, reduced.durability, reduced.time), (36, 5, 6));
+        let rotation = cache.rotation(&reduced);
+        assert_eq!(rotation.iter().map(|(method, _, quality)| (ACTIONS[*method as usize].name, *quality)).collect::<Vec<_>>(),
+            [("Basic Touch", 400), ("Basic Touch", 440)]);
+        let reduced = cache.target_state(&st, 400).unwrap();
+        assert_eq!((reduced.cp, reduced.durability, reduced.time), (18, 3, 3));
+        // a third touch would break the craft
+        assert!(cache.target_state(&st, 1000).is_none());
+    }
+
+    #[test]
+    fn quick_innovation_once_without_innovation() {
+        let cache = cache(5, 100);
+        let st = State {quick_innovation: true, ..state(100, 5)};
+        let (next, _) = cache.apply(&st, action("Quick Innovation")).unwrap();
+        assert_eq!((next.innovation, next.quick_innovation, next.cp), (1, false, 100));
+        assert_eq!(cache.apply(&next, action("Basic Touch")).unwrap().1, 600);
+        assert!(cache.apply(&next, action("Quick Innovation")).is_none());
+        assert!(cache.apply(&State {innovation: 2, ..st}, action("Quick Innovation")).is_none());
+    }
+
+    #[test]
+    fn daring_touch_follows_successful_hasty_touch() {
+        let cache = cache(5, 0);
+        let st = state(0, 5);
+        assert!(cache.apply(&st, action("Daring Touch")).is_none());
+        let (hit, _) = cache.apply_outcome(&st, action("Hasty Touch"), true).unwrap();
+        let (missed, _) = cache.apply_outcome(&st, action("Hasty Touch"), false).unwrap();
+        assert!(hit.expedience && !missed.expedience);
+        // 150% at one stack of Inner Quiet
+        assert_eq!(cache.apply(&hit, action("Daring Touch")).unwrap().1, 660);
+    }
+
+    #[test]
+    fn chance_actions_with_and_without_assume_success() {
+        let rotation = |assume_success: bool| {
+            let mut cache = cache(5, 0);
+            cache.set_available(action_mask(96, false, false));
+            cache.assume_success = assume_success;
+            let (quality, _, completion) = unpack_method(cache.unwrapped_query(&state(0, 5)));
+            let steps: Vec<(&str, u16)> = cache.rotation(&state(0, 5)).iter()
+                .map(|(method, _, quality)| (ACTIONS[*method as usize].name, *quality)).collect();
+            (quality, completion, steps)
+        };
+        assert_eq!(rotation(true), (1060, 1., vec![("Hasty Touch", 400), ("Daring Touch", 660)]));
+        // 0.6 * (400 + 0.6 * 660) after a hit, 0.4 * 0.6 * 400 after a miss
+        assert_eq!(rotation(false), (574, 1., vec![("Hasty Touch", 400), ("Daring Touch", 660)]));
+    }
+
+    #[test]
+    fn long_step_budgets_kept_apart() {
+        let new = |dense: bool| {
+            let mut cache = DPCache::new(5, 54, true, ConditionTable::NormalOnly, 0);
+            cache.set_available(action_mask(5, false, false));
+            if dense {
+                cache.make_dense(&[], 16 * PAGE_SLOTS).unwrap();
+            }
+            cache
+        };
+        for dense in [false, true] {
+            let mut cache = new(dense);
+            for time in [250, 130, 10, 4] {
+                let st = State {time, ..state(54, 5)};
+                assert_eq!(cache.unwrapped_query(&st), new(dense).unwrapped_query(&st), "time {}", time);
+            }
+        }
+    }
+
+    #[test]
+    fn failure_penalty_rejects_risk() {
+        let rotation = |penalty: f64| {
+            // Basic Synthesis, Rapid Synthesis and Hasty Touch are free, with room for two steps
+            let mut cache = DPCache::new(4, 0, false, ConditionTable::NormalOnly, 150);
+            cache.set_available(action_mask(20, true, false));
+            cache.prog_unit = 100;
+            cache.failure_penalty = Some(penalty);
+            let st = State {progress: 150, ..state(0, 4)};
+            let (quality, method, completion) = unpack_method(cache.unwrapped_query(&st));
+            (quality, ACTIONS[method as usize].name, (completion * 100.).round())
+        };
+        // Hasty Touch then Rapid Synthesis: 60% of 400 quality, counted only when Rapid Synthesis completes the craft
+        assert_eq!(rotation(0.), (120, "Hasty Touch", 50.));
+        // two Basic Synthesis always complete it
+        assert_eq!(rotation(1000.), (0, "Basic Synthesis", 100.));
+    }
+
+    #[test]
+    fn carried_buffs_left_running() {
+        // Basic Touch, Waste Not, Veneration and the free Hasty Touch, among others
+        let mut cache = cache(5, 74);
+        cache.set_available(action_mask(20, false, false));
+        let names = |cache: &DPCache, st: &State| cache.rotation(st).iter()
+            .map(|(method, _, quality)| (ACTIONS[*method as usize].name, *quality)).collect::<Vec<_>>();
+        let st = state(74, 5);
+        assert_eq!(unpack_method(cache.unwrapped_query(&st)).0, 1235);
+        assert_eq!(names(&cache, &st)[0], ("Waste Not I", 0));
+        // a finisher taking Waste Not over needs three steps of it left, so it's cast late and fewer touches fit
+        let st = State {carry: Carry::WasteNot, ..state(74, 5)};
+        assert_eq!(unpack_method(cache.unwrapped_query(&st)).0, 664);
+        assert_eq!(names(&cache, &st), [("Basic Touch", 400), ("Waste Not I", 0), ("Hasty Touch", 440)]);
+        assert_eq!(cache.check_endstate(&st).waste_not, 3);
+        // Veneration from the opener wears off, so it's recast for the finisher
+        let st = State {carry: Carry::Veneration, veneration: 3, ..state(36, 5)};
+        assert_eq!(unpack_method(cache.unwrapped_query(&st)).0, 664);
+        assert_eq!(names(&cache, &st), [("Basic Touch", 400), ("Hasty Touch", 440), ("Veneration", 0)]);
+        assert!(cache.check_endstate(&st).can_finish());
+    }
+
+    #[test]
+    fn frontier_rotations() {
+        let mut cache = DPCache::new(5, 36, true, ConditionTable::NormalOnly, 0);
+        cache.set_available(action_mask(5, false, false));
+        let points = cache.frontier(&State {time: 9, ..state(36, 5)});
+        let summary: Vec<(u16, u16, u8)> = points.iter().map(|p| (p.quality, p.cp, p.time)).collect();
+        assert_eq!(summary, [(0, 0, 0), (400, 18, 3), (840, 36, 6)]);
+        for point in points {
+            let rotation = cache.rotation(&point.start);
+            let end = rotation.last().map_or(point.start, |(_, next, _)| *next);
+            assert_eq!(rotation.iter().map(|(_, _, quality)| quality).sum::<u16>(), point.quality);
+            assert_eq!(point.start.cp - end.cp, point.cp);
+            assert_eq!(point.start.time - end.time, point.time);
+            assert!(rotation.iter().all(|(method, _, _)| ACTIONS[*method as usize].name == "Basic Touch"));
         }
-        return State::unpack(prev);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn iterative_matches_recursive() {
+        let new = |iterative: bool| {
+            let mut cache = DPCache::new(3, 20, false, ConditionTable::Expert, 0);
+            cache.set_available(action_mask(100, false, true));
+            cache.iterative = iterative;
+            cache
+        };
+        let (mut recursive, mut iterative) = (new(false), new(true));
+        let conditions = [Condition::Normal, Condition::Good, Condition::Centered, Condition::Sturdy,
+            Condition::Pliant, Condition::Malleable, Condition::Primed];
+        let perfections = [Perfection::Available, Perfection::Active, Perfection::Used];
+        // a fixed linear congruential sequence, so the states are the same on every run
+        let mut seed: u64 = 12345;
+        let mut next = |n: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..12 {
+            let st = State {
+                inner_quiet: next(11) as u8,
+                condition: conditions[next(conditions.len() as u64) as usize],
+                trained_perfection: perfections[next(3) as usize],
+                heart_and_soul: next(2) == 1,
+                tricks: TRICKS_WITHOUT_TIME,
+                ..state(next(21) as u16, 1 + next(3) as u8)
+            };
+            let expected = recursive.unwrapped_query(&st);
+            assert_eq!(iterative.unwrapped_query(&st), expected, "{}", st);
+            let rotation = |cache: &DPCache| cache.rotation(&st).iter().map(|(method, _, quality)| (*method, *quality)).collect::<Vec<_>>();
+            assert_eq!(rotation(&iterative), rotation(&recursive), "{}", st);
+        }
+    }
+
+    #[test]
+    fn dense_store_matches_hashed() {
+        let new = |dense_slots: Option<u64>| {
+            let mut cache = DPCache::new(5, 60, false, ConditionTable::Standard, 0);
+            // Veneration can be cast here, though no action reads it
+            cache.set_available(action_mask(70, false, true));
+            if let Some(slots) = dense_slots {
+                cache.make_dense(&[], slots).unwrap();
+            }
+            cache
+        };
+        let mut hashed = new(None);
+        // with room for most states, and with most sent to the overflow
+        let mut dense = [new(Some(256 * PAGE_SLOTS)), new(Some(2 * PAGE_SLOTS))];
+        for cp in (0..=60).step_by(6) {
+            for durability in 1..=5 {
+                let st = State {heart_and_soul: true, quick_innovation: true, first_step: true,
+                    tricks: TRICKS_WITHOUT_TIME, ..state(cp, durability)};
+                let expected = hashed.unwrapped_query(&st);
+                for cache in &mut dense {
+                    assert_eq!(cache.unwrapped_query(&st), expected, "{}", st);
+                    assert_eq!(cache.unwrapped_query(&State {veneration: 3, ..st}), expected, "{}", st);
+                }
+            }
+        }
+    }
+}
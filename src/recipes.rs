@@ -0,0 +1,589 @@
+//! Recipe data from the CSV files in `data/`: the recipe level table, the per-item recipe table
+//! (`recipes.csv`) and the distinct recipe table (`recipes_filt.csv`). The latter two are built
+//! from the game's tables by [`import`].
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use crate::statline::Recipe;
+
+pub const LEVEL_TABLE_FILE: &str = "RecipeLevelTable.csv";
+pub const ITEM_RECIPES_FILE: &str = "recipes.csv";
+pub const DISTINCT_RECIPES_FILE: &str = "recipes_filt.csv";
+pub const ITEM_TABLE_FILE: &str = "Item.csv";
+pub const RECIPE_TABLE_FILE: &str = "Recipe.csv";
+
+/// Splits CSV text into records of unquoted fields. Quoted fields may hold commas and newlines.
+pub fn parse_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => (),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            },
+            _ => field.push(c)
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Quotes a CSV field if it needs it.
+pub fn quote_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The rows of a CSV file, with fields looked up by column name.
+pub struct Table {
+    pub columns: HashMap<String, usize>,
+    pub rows: Vec<Vec<String>>
+}
+
+impl Table {
+    pub fn new(header: Vec<String>, rows: Vec<Vec<String>>) -> Table {
+        let columns = header.into_iter().enumerate().map(|(i, name)| (name, i)).collect();
+        Table {columns, rows}
+    }
+
+    /// A CSV file with a header line.
+    pub fn read(path: &Path) -> Result<Table, Box<dyn error::Error>> {
+        let text = read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut records = parse_records(&text).into_iter();
+        let header = records.next().ok_or_else(|| format!("{}: empty file", path.display()))?;
+        Ok(Table::new(header, records.collect()))
+    }
+
+    /// A game data table: a key line, the header, a line of column types and a placeholder row 0.
+    /// Returns the table and its column types.
+    pub fn read_game(path: &Path) -> Result<(Table, HashMap<String, String>), Box<dyn error::Error>> {
+        let text = read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut records = parse_records(text.trim_start_matches('\u{feff}')).into_iter();
+        let (Some(key), Some(header), Some(types)) = (records.next(), records.next(), records.next()) else {
+            return Err(format!("{}: missing key, header or type line", path.display()).into())
+        };
+        if key.first().map(String::as_str) != Some("key") {
+            return Err(format!("{}: first line is not a key line", path.display()).into())
+        }
+        if header.len() != key.len() || types.len() != key.len() {
+            return Err(format!("{}: key, header and type lines differ in length", path.display()).into())
+        }
+        let types = header.iter().cloned().zip(types).collect();
+        let rows = records.filter(|row| row[0] != "0").collect();
+        Ok((Table::new(header, rows), types))
+    }
+
+    pub fn column(&self, name: &str) -> Result<usize, Box<dyn error::Error>> {
+        self.columns.get(name).copied().ok_or_else(|| format!("missing column {}", name).into())
+    }
+
+    pub fn get<'a>(&self, row: &'a [String], name: &str) -> Result<&'a str, Box<dyn error::Error>> {
+        let i = self.column(name)?;
+        row.get(i).map(String::as_str).ok_or_else(|| format!("row {} has no {}", row[0], name).into())
+    }
+
+    pub fn parse<T: FromStr>(&self, row: &[String], name: &str) -> Result<T, Box<dyn error::Error>> {
+        let field = self.get(row, name)?;
+        field.parse().map_err(|_| format!("row {}: bad {} '{}'", row[0], name, field).into())
+    }
+}
+
+/// A row of the recipe level table.
+#[derive(Clone, Debug)]
+pub struct RecipeLevel {
+    pub rlvl: u16,
+    pub lvl: u8,
+    pub stars: u8,
+    pub prog: u32,
+    pub qual: u32,
+    pub dur: u8,
+    pub pdiv: u16,
+    pub qdiv: u16,
+    pub pmod: u16,
+    pub qmod: u16
+}
+
+/// A recipe's progress, quality and durability factors, in percent of its level's.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Factors {
+    pub prog: u32,
+    pub qual: u32,
+    pub dur: u32
+}
+
+impl RecipeLevel {
+    pub fn recipe(&self, factors: Factors, reqqual: Option<u32>) -> Recipe {
+        Recipe {
+            rlvl: self.rlvl,
+            prog: factors.prog * self.prog / 100,
+            qual: factors.qual * self.qual / 100,
+            dur: (factors.dur * self.dur as u32 / 100) as u8,
+            pdiv: self.pdiv,
+            qdiv: self.qdiv,
+            pmod: self.pmod,
+            qmod: self.qmod,
+            reqqual
+        }
+    }
+}
+
+/// A recipe from `recipes.csv` or `recipes_filt.csv`.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub lvl: u8,
+    pub stars: u8,
+    pub expert: bool,
+    pub recipe: Recipe
+}
+
+/// Name `recipes_filt.csv` gives the distinct recipe with these factors.
+pub fn distinct_name(rlvl: u16, factors: Factors) -> String {
+    format!("rlvl{} {}/{}/{}", rlvl, factors.prog, factors.qual, factors.dur)
+}
+
+fn read_entries(path: &Path) -> Result<Vec<Entry>, Box<dyn error::Error>> {
+    let table = Table::read(path)?;
+    table.rows.iter().map(|row| {
+        let reqqual: u32 = table.parse(row, "reqqual")?;
+        Ok(Entry {
+            name: table.get(row, "name")?.to_string(),
+            lvl: table.parse(row, "lvl")?,
+            stars: table.parse(row, "stars")?,
+            expert: table.get(row, "expert")? == "True",
+            recipe: Recipe {
+                rlvl: table.parse(row, "rlvl")?,
+                prog: table.parse(row, "prog")?,
+                qual: table.parse(row, "qual")?,
+                dur: table.parse(row, "dur")?,
+                pdiv: table.parse(row, "pdiv")?,
+                qdiv: table.parse(row, "qdiv")?,
+                pmod: table.parse(row, "pmod")?,
+                qmod: table.parse(row, "qmod")?,
+                reqqual: if reqqual > 0 {Some(reqqual)} else {None}
+            }
+        })
+    }).collect::<Result<_, Box<dyn error::Error>>>().map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// Checks that a game table has the columns we read, with the types we expect.
+fn check_types(path: &Path, types: &HashMap<String, String>, expected: &[(&str, &str)]) -> Result<(), Box<dyn error::Error>> {
+    for &(column, expected) in expected {
+        match types.get(column) {
+            None => return Err(format!("{}: missing column {}", path.display(), column).into()),
+            Some(found) if !found.starts_with(expected) =>
+                return Err(format!("{}: column {} is {}, expected {}", path.display(), column, found, expected).into()),
+            _ => ()
+        }
+    }
+    Ok(())
+}
+
+pub fn read_levels(path: &Path) -> Result<HashMap<u16, RecipeLevel>, Box<dyn error::Error>> {
+    let (table, types) = Table::read_game(path)?;
+    check_types(path, &types, &[
+        ("#", "int32"), ("ClassJobLevel", "byte"), ("Stars", "byte"), ("Difficulty", "uint16"), ("Quality", "uint32"),
+        ("ProgressDivider", "byte"), ("QualityDivider", "byte"), ("ProgressModifier", "byte"), ("QualityModifier", "byte"),
+        ("Durability", "uint16")
+    ])?;
+    table.rows.iter().map(|row| {
+        let level = RecipeLevel {
+            rlvl: table.parse(row, "#")?,
+            lvl: table.parse(row, "ClassJobLevel")?,
+            stars: table.parse(row, "Stars")?,
+            prog: table.parse(row, "Difficulty")?,
+            qual: table.parse(row, "Quality")?,
+            dur: table.parse(row, "Durability")?,
+            pdiv: table.parse(row, "ProgressDivider")?,
+            qdiv: table.parse(row, "QualityDivider")?,
+            pmod: table.parse(row, "ProgressModifier")?,
+            qmod: table.parse(row, "QualityModifier")?
+        };
+        Ok((level.rlvl, level))
+    }).collect::<Result<_, Box<dyn error::Error>>>().map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+fn write_entries(path: &Path, entries: &[Entry]) -> Result<(), Box<dyn error::Error>> {
+    let mut out = String::from("name,rlvl,lvl,stars,prog,qual,dur,pdiv,qdiv,pmod,qmod,reqqual,expert\r\n");
+    for entry in entries {
+        let recipe = &entry.recipe;
+        out += &format!("{},{},{},{},{},{},{},{},{},{},{},{},{}\r\n", quote_field(&entry.name), recipe.rlvl, entry.lvl, entry.stars,
+            recipe.prog, recipe.qual, recipe.dur, recipe.pdiv, recipe.qdiv, recipe.pmod, recipe.qmod, recipe.reqqual.unwrap_or(0),
+            if entry.expert {"True"} else {"False"});
+    }
+    write(path, out).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// The fields of a `Recipe.csv` row the import uses.
+struct RecipeRow {
+    item: u32,
+    rlvl: u16,
+    factors: Factors,
+    reqqual: u32,
+    expert: bool
+}
+
+impl RecipeRow {
+    /// None for recipes without a result item.
+    fn read(table: &Table, row: &[String]) -> Result<Option<RecipeRow>, Box<dyn error::Error>> {
+        let item = table.parse(row, "Item{Result}")?;
+        if item == 0 {
+            return Ok(None)
+        }
+        Ok(Some(RecipeRow {
+            item,
+            rlvl: table.parse(row, "RecipeLevelTable")?,
+            factors: Factors {
+                prog: table.parse(row, "DifficultyFactor")?,
+                qual: table.parse(row, "QualityFactor")?,
+                dur: table.parse(row, "DurabilityFactor")?
+            },
+            reqqual: table.parse(row, "RequiredQuality")?,
+            expert: table.get(row, "IsExpert")? == "True"
+        }))
+    }
+}
+
+/// Builds `recipes.csv` and `recipes_filt.csv` in `out_dir` from the game's `Item.csv`,
+/// `Recipe.csv` and `RecipeLevelTable.csv` in `game_dir`. Without `Item.csv` only the distinct
+/// recipes are written. Returns how many per-item and distinct recipes were written.
+pub fn import(game_dir: &Path, out_dir: &Path) -> Result<(Option<usize>, usize), Box<dyn error::Error>> {
+    let levels = read_levels(&game_dir.join(LEVEL_TABLE_FILE))?;
+
+    let item_path = game_dir.join(ITEM_TABLE_FILE);
+    let items = if item_path.exists() {
+        let (table, types) = Table::read_game(&item_path)?;
+        check_types(&item_path, &types, &[("#", "int32"), ("Name", "str")])?;
+        let names = table.rows.iter()
+            .map(|row| Ok((table.parse::<u32>(row, "#")?, table.get(row, "Name")?.to_string())))
+            .collect::<Result<HashMap<_, _>, Box<dyn error::Error>>>()
+            .map_err(|err| format!("{}: {}", item_path.display(), err))?;
+        Some(names)
+    } else {
+        None
+    };
+
+    let recipe_path = game_dir.join(RECIPE_TABLE_FILE);
+    let (table, types) = Table::read_game(&recipe_path)?;
+    check_types(&recipe_path, &types, &[
+        ("RecipeLevelTable", "RecipeLevelTable"), ("Item{Result}", "Item"), ("DifficultyFactor", "uint16"),
+        ("QualityFactor", "uint16"), ("DurabilityFactor", "uint16"), ("RequiredQuality", "uint32"), ("IsExpert", "bit")
+    ])?;
+    let mut per_item = Vec::new();
+    let mut distinct = Vec::new();
+    let mut registered = HashSet::new();
+    for row in &table.rows {
+        let Some(RecipeRow {item, rlvl, factors, reqqual, expert}) =
+            RecipeRow::read(&table, row).map_err(|err| format!("{}: {}", recipe_path.display(), err))? else {
+            continue
+        };
+        let level = levels.get(&rlvl).ok_or_else(|| format!("{}: row {} has unknown recipe level {}", recipe_path.display(), row[0], rlvl))?;
+        let entry = |name| Entry {
+            name,
+            lvl: level.lvl,
+            stars: level.stars,
+            expert,
+            recipe: level.recipe(factors, if reqqual > 0 {Some(reqqual)} else {None})
+        };
+        if let Some(names) = &items {
+            per_item.push(entry(names.get(&item).cloned().unwrap_or_default()));
+        }
+        if registered.insert((rlvl, factors, expert)) {
+            distinct.push(entry(distinct_name(rlvl, factors)));
+        }
+    }
+    distinct.sort_by_key(|entry| (entry.recipe.rlvl, entry.recipe.prog, entry.recipe.qual));
+
+    if items.is_some() {
+        write_entries(&out_dir.join(ITEM_RECIPES_FILE), &per_item)?;
+    }
+    write_entries(&out_dir.join(DISTINCT_RECIPES_FILE), &distinct)?;
+    Ok((items.map(|_| per_item.len()), distinct.len()))
+}
+
+pub struct RecipeDb {
+    pub levels: HashMap<u16, RecipeLevel>,
+    pub items: Vec<Entry>,
+    pub distinct: Vec<Entry>
+}
+
+impl RecipeDb {
+    pub fn load(dir: &Path) -> Result<RecipeDb, Box<dyn error::Error>> {
+        Ok(RecipeDb {
+            levels: read_levels(&dir.join(LEVEL_TABLE_FILE))?,
+            items: read_entries(&dir.join(ITEM_RECIPES_FILE))?,
+            distinct: read_entries(&dir.join(DISTINCT_RECIPES_FILE))?
+        })
+    }
+
+    pub fn level(&self, rlvl: u16) -> Option<&RecipeLevel> {
+        self.levels.get(&rlvl)
+    }
+
+    /// The recipe crafting an item, matching the name exactly or else ignoring case.
+    pub fn by_name(&self, name: &str) -> Option<&Entry> {
+        self.items.iter().find(|entry| entry.name == name)
+            .or_else(|| self.items.iter().find(|entry| entry.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// The distinct recipes at a recipe level.
+    pub fn by_rlvl(&self, rlvl: u16) -> Vec<&Entry> {
+        self.distinct.iter().filter(|entry| entry.recipe.rlvl == rlvl).collect()
+    }
+
+    /// The recipe with these factors, built from the level table if no recipe uses them.
+    pub fn by_factors(&self, rlvl: u16, factors: Factors, expert: bool) -> Option<Entry> {
+        let name = distinct_name(rlvl, factors);
+        if let Some(entry) = self.distinct.iter().find(|entry| entry.name == name && entry.expert == expert) {
+            return Some(entry.clone())
+        }
+        self.level(rlvl).map(|level| Entry {
+            name,
+            lvl: level.lvl,
+            stars: level.stars,
+            expert,
+            recipe: level.recipe(factors, None)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::PathBuf;
+
+    const LEVELS: &str = "\u{feff}key,0,1,2,3,4,5,6,7,8,9,10,11
+#,ClassJobLevel,Stars,SuggestedCraftsmanship,SuggestedControl,Difficulty,Quality,ProgressDivider,QualityDivider,ProgressModifier,QualityModifier,Durability,ConditionsFlag
+int32,byte,byte,uint16,uint16,uint16,uint32,byte,byte,byte,byte,uint16,uint16
+0,0,0,10000,10000,100,100,50,30,100,100,100,15
+1,1,0,22,11,19,100,50,30,100,100,60,15
+640,90,0,3500,3300,6600,12000,130,115,80,70,80,15
+";
+
+    const ITEMS: &str = "name,rlvl,lvl,stars,prog,qual,dur,pdiv,qdiv,pmod,qmod,reqqual,expert
+Bronze Ingot,1,1,0,9,80,40,50,30,100,100,0,False
+\"Ingot, Grade 4\",640,90,0,3300,7200,40,130,115,80,70,5000,True
+";
+
+    const DISTINCT: &str = "name,rlvl,lvl,stars,prog,qual,dur,pdiv,qdiv,pmod,qmod,reqqual,expert
+rlvl1 50/80/67,1,1,0,9,80,40,50,30,100,100,0,False
+rlvl1 50/100/67,1,1,0,9,100,40,50,30,100,100,0,False
+rlvl640 50/60/50,640,90,0,3300,7200,40,130,115,80,70,5000,True
+";
+
+    const GAME_RECIPES: &str = "\u{feff}key,0,1,2,3,4,5,6
+#,RecipeLevelTable,Item{Result},DifficultyFactor,QualityFactor,DurabilityFactor,RequiredQuality,IsExpert
+int32,RecipeLevelTable,Item,uint16,uint16,uint16,uint32,bit&08
+0,0,0,100,100,100,0,False
+1,1,5056,50,80,67,0,False
+2,1,5057,50,80,67,0,False
+3,640,44000,50,60,50,5000,True
+4,640,44001,50,60,50,0,False
+5,1,0,100,100,100,0,False
+";
+
+    const GAME_ITEMS: &str = "key,0
+#,Name
+int32,str
+0,
+5056,Bronze Ingot
+5057,\"Ingot, Bronze\"
+44000,Grade 4 Ingot
+";
+
+    /// A fresh temporary directory of CSV files, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Fixture {
+            let dir = std::env::temp_dir().join(format!("qualsim-{}-{}", std::process::id(), name));
+            let _ = remove_dir_all(&dir);
+            create_dir_all(&dir).unwrap();
+            for (file, text) in files {
+                write(dir.join(file), text).unwrap();
+            }
+            Fixture(dir)
+        }
+
+        /// Recipe data as `RecipeDb::load` reads it.
+        fn data(name: &str, items: &str, distinct: &str) -> Fixture {
+            Fixture::new(name, &[(LEVEL_TABLE_FILE, LEVELS), (ITEM_RECIPES_FILE, items), (DISTINCT_RECIPES_FILE, distinct)])
+        }
+
+        /// Game tables as `import` reads them, without an item table if `items` is None.
+        fn game(name: &str, recipes: &str, items: Option<&str>) -> Fixture {
+            let mut files = vec![(LEVEL_TABLE_FILE, LEVELS), (RECIPE_TABLE_FILE, recipes)];
+            files.extend(items.map(|items| (ITEM_TABLE_FILE, items)));
+            Fixture::new(name, &files)
+        }
+
+        /// A recipe level table alone.
+        fn levels(name: &str, text: &str) -> Fixture {
+            Fixture::new(name, &[(LEVEL_TABLE_FILE, text)])
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+
+    fn db(name: &str) -> RecipeDb {
+        RecipeDb::load(Fixture::data(name, ITEMS, DISTINCT).path()).unwrap()
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let records = parse_records("a,\"b, c\",\"say \"\"hi\"\"\"\r\n\"two\nlines\",\r\n\n");
+        assert_eq!(records, [vec!["a", "b, c", "say \"hi\""], vec!["two\nlines", ""]]);
+        assert_eq!(quote_field("b, c"), "\"b, c\"");
+        assert_eq!(quote_field("plain"), "plain");
+    }
+
+    #[test]
+    fn entries_loaded() {
+        let db = db("entries");
+        assert_eq!(db.levels.len(), 2);
+        assert_eq!(db.level(640).map(|level| (level.pdiv, level.qdiv, level.pmod, level.qmod)), Some((130, 115, 80, 70)));
+        let entry = &db.items[1];
+        assert_eq!((entry.name.as_str(), entry.lvl, entry.expert), ("Ingot, Grade 4", 90, true));
+        assert_eq!((entry.recipe.prog, entry.recipe.qual, entry.recipe.dur, entry.recipe.reqqual), (3300, 7200, 40, Some(5000)));
+        assert_eq!(db.items[0].recipe.reqqual, None);
+    }
+
+    #[test]
+    fn bad_field_named() {
+        let items = ITEMS.replace("Bronze Ingot,1,1,0,9,", "Bronze Ingot,1,1,0,nine,");
+        let dir = Fixture::data("bad-field", &items, DISTINCT);
+        let err = RecipeDb::load(dir.path()).err().unwrap().to_string();
+        assert!(err.contains(ITEM_RECIPES_FILE) && err.contains("bad prog 'nine'"), "{}", err);
+    }
+
+    #[test]
+    fn missing_column_named() {
+        let distinct = DISTINCT.replace(",expert\n", "\n");
+        let dir = Fixture::data("missing-column", ITEMS, &distinct);
+        let err = RecipeDb::load(dir.path()).err().unwrap().to_string();
+        assert!(err.contains("missing column expert"), "{}", err);
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        let db = db("by-name");
+        assert_eq!(db.by_name("Bronze Ingot").map(|entry| entry.recipe.prog), Some(9));
+        assert_eq!(db.by_name("ingot, grade 4").map(|entry| entry.recipe.prog), Some(3300));
+        assert!(db.by_name("Iron Ingot").is_none());
+    }
+
+    #[test]
+    fn lookup_by_rlvl() {
+        let db = db("by-rlvl");
+        let names: Vec<&str> = db.by_rlvl(1).iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["rlvl1 50/80/67", "rlvl1 50/100/67"]);
+        assert!(db.by_rlvl(2).is_empty());
+    }
+
+    #[test]
+    fn lookup_by_factors() {
+        let db = db("by-factors");
+        let expert = db.by_factors(640, Factors {prog: 50, qual: 60, dur: 50}, true).unwrap();
+        assert_eq!(expert.recipe.reqqual, Some(5000));
+        // the same factors without expert conditions aren't a recipe, so they're built from the level table
+        let normal = db.by_factors(640, Factors {prog: 50, qual: 60, dur: 50}, false).unwrap();
+        assert_eq!((normal.expert, normal.recipe.prog, normal.recipe.qual, normal.recipe.dur, normal.recipe.reqqual),
+            (false, 3300, 7200, 40, None));
+        let built = db.by_factors(1, Factors {prog: 100, qual: 100, dur: 100}, false).unwrap();
+        assert_eq!((built.name.as_str(), built.recipe.prog, built.recipe.qual, built.recipe.dur), ("rlvl1 100/100/100", 19, 100, 60));
+        assert!(db.by_factors(2, Factors {prog: 100, qual: 100, dur: 100}, false).is_none());
+    }
+
+    #[test]
+    fn game_table_read() {
+        let dir = Fixture::levels("game-table", LEVELS);
+        let (table, types) = Table::read_game(&dir.path().join(LEVEL_TABLE_FILE)).unwrap();
+        // the byte order mark is dropped from the key line, and the placeholder row 0 is skipped
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(types["Durability"], "uint16");
+        assert_eq!(table.get(&table.rows[1], "Difficulty").unwrap(), "6600");
+    }
+
+    #[test]
+    fn game_table_checked() {
+        let lines: Vec<&str> = LEVELS.lines().collect();
+        let cases = [
+            ("no-key", lines[1..].join("\n"), "first line is not a key line"),
+            ("no-types", lines[..2].join("\n"), "missing key, header or type line"),
+            ("short-header", LEVELS.replace(",ConditionsFlag", ""), "differ in length"),
+            ("wrong-type", LEVELS.replace("int32,byte,byte", "int32,str,byte"), "column ClassJobLevel is str, expected byte")
+        ];
+        for (name, text, expected) in cases {
+            let dir = Fixture::levels(name, &text);
+            let err = read_levels(&dir.path().join(LEVEL_TABLE_FILE)).err().unwrap().to_string();
+            assert!(err.contains(expected), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn import_dedups_recipes() {
+        let game = Fixture::game("import-game", GAME_RECIPES, Some(GAME_ITEMS));
+        let out = Fixture::levels("import-out", LEVELS);
+        // every recipe with a result item, but only one per level, factors and expert flag
+        assert_eq!(import(game.path(), out.path()).unwrap(), (Some(4), 3));
+        let db = RecipeDb::load(out.path()).unwrap();
+        let names: Vec<&str> = db.distinct.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["rlvl1 50/80/67", "rlvl640 50/60/50", "rlvl640 50/60/50"]);
+        assert_eq!(db.distinct.iter().filter(|entry| entry.expert).count(), 1);
+        let ingot = db.by_name("Ingot, Bronze").unwrap();
+        assert_eq!((ingot.recipe.prog, ingot.recipe.qual, ingot.recipe.dur), (9, 80, 40));
+        let expert = db.by_name("Grade 4 Ingot").unwrap();
+        assert_eq!((expert.expert, expert.recipe.prog, expert.recipe.reqqual), (true, 3300, Some(5000)));
+        // an item missing from Item.csv keeps its recipe under an empty name
+        assert!(db.items.iter().any(|entry| entry.name.is_empty() && entry.recipe.rlvl == 640 && !entry.expert));
+    }
+
+    #[test]
+    fn import_without_items() {
+        let game = Fixture::game("import-no-items", GAME_RECIPES, None);
+        let out = Fixture::new("import-no-items-out", &[]);
+        assert_eq!(import(game.path(), out.path()).unwrap(), (None, 3));
+        assert!(!out.path().join(ITEM_RECIPES_FILE).exists());
+    }
+
+    #[test]
+    fn import_rejects_unknown_level() {
+        let recipes = GAME_RECIPES.replace("4,640,44001", "4,641,44001");
+        let game = Fixture::game("import-unknown-level", &recipes, None);
+        let out = Fixture::new("import-unknown-level-out", &[]);
+        let err = import(game.path(), out.path()).err().unwrap().to_string();
+        assert!(err.contains("row 4 has unknown recipe level 641"), "{}", err);
+    }
+}
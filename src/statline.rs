@@ -1,49 +1,143 @@
+use std::cmp;
+use std::fmt;
+use serde::{Serialize, Deserialize};
 
 
+
+#[derive(Clone, Copy, Debug)]
 pub struct CrafterStats {
-    lvl: u8,
-    cp: u16,
-    cms: u16,
-    ctrl: u16
+    pub lvl: u8,
+    pub cp: u16,
+    pub cms: u16,
+    pub ctrl: u16
 }
 
+#[derive(Clone, Debug)]
 pub struct Recipe {
-    rlvl: u16,
-    prog: u32,
-    qual: u32,
-    dur: u8,
-    pdiv: u16,
-    qdiv: u16,
-    pmod: u16,
-    qmod: u16,
-    reqqual: Option<u32>
+    pub rlvl: u16,
+    pub prog: u32,
+    pub qual: u32,
+    pub dur: u8,
+    pub pdiv: u16,
+    pub qdiv: u16,
+    pub pmod: u16,
+    pub qmod: u16,
+    pub reqqual: Option<u32>
 }
 
 pub struct CombinedCraftInfo {
-    prog: u32,
-    qual: u32,
-    dur: u8,
-    cp: u16,
-    p100: u16,
-    q100: u16
-}
-
-pub fn clamp(v: u8, lb: u8, ub: u8) -> u8 {
-    if v < lb {
-        lb
-    } else if v > ub {
-        ub
-    } else {
-        v
+    pub prog: u32,
+    pub qual: u32,
+    pub dur: u8,
+    pub cp: u16,
+    pub p100: u16,
+    pub q100: u16
+}
+
+/// A consumable's bonus to one stat: `percent` of the base stat, up to `cap`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Bonus {
+    pub percent: u16,
+    pub cap: u16
+}
+
+impl Bonus {
+    fn amount(&self, base: u16) -> u16 {
+        cmp::min(base as u32 * self.percent as u32 / 100, self.cap as u32) as u16
+    }
+}
+
+/// The bonuses of a consumable at one quality.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Bonuses {
+    #[serde(default)]
+    pub cms: Option<Bonus>,
+    #[serde(default)]
+    pub ctrl: Option<Bonus>,
+    #[serde(default)]
+    pub cp: Option<Bonus>
+}
+
+/// A food or a medicine. One of each can be active at a time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Consumable {
+    pub name: String,
+    #[serde(default)]
+    pub medicine: bool,
+    pub nq: Bonuses,
+    pub hq: Bonuses,
+    // price of one, to pick the cheapest that reaches a target
+    #[serde(default)]
+    pub nq_cost: u32,
+    #[serde(default)]
+    pub hq_cost: u32
+}
+
+impl Consumable {
+    /// The consumable at NQ and at HQ.
+    pub fn buffs(&self) -> [Buff<'_>; 2] {
+        [Buff {consumable: self, hq: false}, Buff {consumable: self, hq: true}]
+    }
+}
+
+/// A consumable at one quality.
+#[derive(Clone, Copy, Debug)]
+pub struct Buff<'a> {
+    pub consumable: &'a Consumable,
+    pub hq: bool
+}
+
+impl Buff<'_> {
+    pub fn bonuses(&self) -> &Bonuses {
+        if self.hq {&self.consumable.hq} else {&self.consumable.nq}
+    }
+
+    pub fn cost(&self) -> u32 {
+        if self.hq {self.consumable.hq_cost} else {self.consumable.nq_cost}
+    }
+}
+
+impl fmt::Display for Buff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.consumable.name, if self.hq {" (HQ)"} else {""})
+    }
+}
+
+/// Every choice of at most one food and at most one medicine, starting with neither.
+pub fn buff_sets(consumables: &[Consumable]) -> Vec<Vec<Buff<'_>>> {
+    let buffs = |medicine: bool| consumables.iter().filter(move |c| c.medicine == medicine).flat_map(|c| c.buffs());
+    let foods = [None].into_iter().chain(buffs(false).map(Some));
+    foods.flat_map(|food| [None].into_iter().chain(buffs(true).map(Some))
+        .map(move |medicine| food.into_iter().chain(medicine).collect())).collect()
+}
+
+impl CrafterStats {
+    /// These base stats with the buffs active. Each bonus is taken from the base stats.
+    pub fn with(&self, buffs: &[Buff]) -> CrafterStats {
+        let total = |stat: fn(&Bonuses) -> Option<Bonus>, base: u16|
+            base + buffs.iter().filter_map(|buff| stat(buff.bonuses())).map(|bonus| bonus.amount(base)).sum::<u16>();
+        CrafterStats {
+            lvl: self.lvl,
+            cp: total(|b| b.cp, self.cp),
+            cms: total(|b| b.cms, self.cms),
+            ctrl: total(|b| b.ctrl, self.ctrl)
+        }
     }
 }
 
+/// Progress and quality modifiers, in percent, for a crafter of level `lvl`, from 1 to
+/// `MAX_LEVEL`. They only apply when the crafter's level is not above the recipe's.
+fn modifiers(recipe: &Recipe, lvl: u8) -> (u64, u64) {
+    let clvl = CLVL_TABLE[lvl as usize - 1];
+    if clvl <= recipe.rlvl {(recipe.pmod as u64, recipe.qmod as u64)} else {(100, 100)}
+}
+
 pub fn combine_info(recipe: &Recipe, stats: &CrafterStats) -> CombinedCraftInfo {
-    let clvl = CLVL_TABLE[clamp(stats.lvl-1, 0, 89) as usize];
-    let p100num: u64 = (stats.cms as u64 * 10 + 2 * recipe.pdiv as u64) * (if clvl <= recipe.rlvl {recipe.pmod as u64} else {100});
+    let (pmod, qmod) = modifiers(recipe, stats.lvl);
+    let p100num: u64 = (stats.cms as u64 * 10 + 2 * recipe.pdiv as u64) * pmod;
     let p100denom: u64 = recipe.pdiv as u64 * 100;
     let p100 = (p100num / p100denom) as u16;
-    let q100num: u64 = (stats.ctrl as u64 * 10 + 35 * recipe.qdiv as u64) * (if clvl <= recipe.rlvl {recipe.qmod as u64} else {100});
+    let q100num: u64 = (stats.ctrl as u64 * 10 + 35 * recipe.qdiv as u64) * qmod;
     let q100denom: u64 = recipe.qdiv as u64 * 100;
     let q100 = (q100num / q100denom) as u16;
     
@@ -57,15 +151,86 @@ pub fn combine_info(recipe: &Recipe, stats: &CrafterStats) -> CombinedCraftInfo
     }
 }
 
+/// Least Craftsmanship at which a level `lvl` crafter gets `p100` progress per 100% efficiency.
+pub fn min_craftsmanship(recipe: &Recipe, lvl: u8, p100: u16) -> u16 {
+    let (pmod, _) = modifiers(recipe, lvl);
+    let pdiv = recipe.pdiv as u64;
+    ((p100 as u64 * pdiv * 100).div_ceil(pmod).saturating_sub(2 * pdiv)).div_ceil(10) as u16
+}
+
+/// Least Control at which a level `lvl` crafter gets `q100` quality per 100% efficiency.
+pub fn min_control(recipe: &Recipe, lvl: u8, q100: u16) -> u16 {
+    let (_, qmod) = modifiers(recipe, lvl);
+    let qdiv = recipe.qdiv as u64;
+    ((q100 as u64 * qdiv * 100).div_ceil(qmod).saturating_sub(35 * qdiv)).div_ceil(10) as u16
+}
+
+// Recipe level of each character level, from data/CharacterLevelTable.csv
+include!(concat!(env!("OUT_DIR"), "/clvl_table.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bonuses(cms: Option<(u16, u16)>, ctrl: Option<(u16, u16)>, cp: Option<(u16, u16)>) -> Bonuses {
+        let bonus = |b: Option<(u16, u16)>| b.map(|(percent, cap)| Bonus {percent, cap});
+        Bonuses {cms: bonus(cms), ctrl: bonus(ctrl), cp: bonus(cp)}
+    }
+
+    fn consumable(name: &str, medicine: bool, nq: Bonuses, hq: Bonuses) -> Consumable {
+        Consumable {name: name.to_string(), medicine, nq, hq, nq_cost: 1, hq_cost: 3}
+    }
+
+    fn stats() -> CrafterStats {
+        CrafterStats {lvl: 100, cp: 600, cms: 4000, ctrl: 3900}
+    }
+
+    #[test]
+    fn bonus_capped() {
+        let bonus = Bonus {percent: 4, cap: 70};
+        assert_eq!(bonus.amount(1000), 40);
+        assert_eq!(bonus.amount(1749), 69);
+        assert_eq!(bonus.amount(4000), 70);
+    }
+
+    #[test]
+    fn food_at_nq_and_hq() {
+        let food = consumable("Food", false,
+            bonuses(None, Some((4, 56)), Some((21, 58))), bonuses(None, Some((5, 70)), Some((26, 78))));
+        let [nq, hq] = food.buffs();
+        let with_nq = stats().with(&[nq]);
+        assert_eq!((with_nq.cms, with_nq.ctrl, with_nq.cp), (4000, 3956, 658));
+        let with_hq = stats().with(&[hq]);
+        assert_eq!((with_hq.cms, with_hq.ctrl, with_hq.cp), (4000, 3970, 678));
+        // a low stat stays under the cap
+        let low = CrafterStats {cp: 200, ..stats()}.with(&[hq]);
+        assert_eq!(low.cp, 252);
+    }
+
+    #[test]
+    fn food_and_medicine_from_base_stats() {
+        let food = consumable("Food", false, bonuses(None, None, Some((20, 100))), bonuses(None, None, Some((25, 200))));
+        let medicine = consumable("Medicine", true, bonuses(None, None, Some((5, 15))), bonuses(None, None, Some((6, 21))));
+        let [_, food] = food.buffs();
+        let [_, medicine] = medicine.buffs();
+        // both bonuses are a share of the 600 base CP, not of each other's result
+        assert_eq!(stats().with(&[food, medicine]).cp, 600 + 150 + 21);
+    }
 
-pub const CLVL_TABLE: [u16; 90] = [
-    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 
-    11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 
-    21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 
-    31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 
-    41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 
-    120, 125, 130, 133, 136, 139, 142, 145, 148, 150, 
-    260, 265, 270, 273, 276, 279, 282, 285, 288, 290, 
-    390, 395, 400, 403, 406, 409, 412, 415, 418, 420, 
-    517, 520, 525, 530, 535, 540, 545, 550, 555, 560
-];
\ No newline at end of file
+    #[test]
+    fn buff_sets_pair_food_and_medicine() {
+        let nothing = Bonuses::default();
+        let consumables = [
+            consumable("Food A", false, nothing, nothing),
+            consumable("Food B", false, nothing, nothing),
+            consumable("Medicine", true, nothing, nothing)
+        ];
+        let sets = buff_sets(&consumables);
+        // (none + 4 foods) x (none + 2 medicines)
+        assert_eq!(sets.len(), 15);
+        assert!(sets[0].is_empty());
+        assert!(sets.iter().all(|set| set.iter().filter(|buff| buff.consumable.medicine).count() <= 1
+            && set.iter().filter(|buff| !buff.consumable.medicine).count() <= 1));
+        assert_eq!(sets.iter().filter(|set| set.len() == 2).count(), 8);
+    }
+}